@@ -0,0 +1,78 @@
+use std::fmt;
+
+use refactoring_rustack::{Element, Parser};
+
+/// ソースコードの構文の誤り
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.line, self.message)
+    }
+}
+
+/// ソースコードをパースして構文の誤りを返す (実行はしない)
+pub fn check_source(source: &str) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut parser = Parser::new();
+    for line in source.lines() {
+        for element in parser.parse(line.to_string()) {
+            inspect(&element, parser.line(), &mut problems);
+        }
+    }
+    if let Some(line) = parser.open_block_line() {
+        problems.push(Problem {
+            line,
+            message: "unclosed block".to_string(),
+        });
+    }
+    problems
+}
+
+/// 要素とブロックの中の要素を調べる
+fn inspect(element: &Element, line: usize, problems: &mut Vec<Problem>) {
+    match element {
+        Element::Block(block) => block
+            .iter()
+            .for_each(|(element, line)| inspect(element, line, problems)),
+        Element::Operation(word) if word == "}" => problems.push(Problem {
+            line,
+            message: "unmatched }".to_string(),
+        }),
+        Element::Operation(word) if word.starts_with('"') => problems.push(Problem {
+            line,
+            message: format!("invalid string literal {word}"),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_source, Problem};
+
+    #[test]
+    fn test_check() {
+        assert_eq!(
+            check_source(&std::fs::read_to_string("scripts/fibonacci.txt").unwrap()),
+            vec![]
+        );
+
+        let problem = |line: usize, message: &str| Problem {
+            line,
+            message: message.to_string(),
+        };
+        assert_eq!(
+            check_source("1 }\n{ \"a\\q\" }\n/f {\n  {\n}"),
+            vec![
+                problem(1, "unmatched }"),
+                problem(2, "invalid string literal \"a\\q\""),
+                problem(3, "unclosed block"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use super::repl::COMMANDS;
+
+/// 対話モードで語とファイルのパスを補完する
+pub struct ReplHelper {
+    words: Vec<String>,
+    filenames: FilenameCompleter,
+}
+
+impl ReplHelper {
+    /// 補完する語を持たないヘルパーを生成する
+    pub fn new() -> Self {
+        Self {
+            words: vec![],
+            filenames: FilenameCompleter::new(),
+        }
+    }
+
+    /// 補完する語を設定する
+    pub fn set_words(&mut self, words: Vec<String>) {
+        self.words = words;
+    }
+
+    /// 行の `pos` までを補完し、置き換える位置と候補を返す
+    fn candidates(&self, line: &str, pos: usize) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let input = &line[..pos];
+        if input.trim_start().starts_with(":load ") {
+            return self.filenames.complete_path(line, pos);
+        }
+
+        let start = input
+            .rfind(char::is_whitespace)
+            .map_or(0, |index| index + 1);
+        let word = &input[start..];
+        if let Some(command) = word.strip_prefix(':') {
+            if input[..start].trim().is_empty() {
+                return Ok((start + 1, matching(COMMANDS.iter().copied(), command)));
+            }
+        }
+
+        // シンボルは `/` の後ろの名前を補完する
+        let (start, prefix) = match word.strip_prefix('/') {
+            Some(name) => (start + 1, name),
+            None => (start, word),
+        };
+        Ok((
+            start,
+            matching(self.words.iter().map(String::as_str), prefix),
+        ))
+    }
+}
+
+/// `prefix` から始まる候補を返す
+fn matching<'a>(names: impl Iterator<Item = &'a str>, prefix: &str) -> Vec<Pair> {
+    names
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| Pair {
+            display: name.to_string(),
+            replacement: name.to_string(),
+        })
+        .collect()
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        self.candidates(line, pos)
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplHelper;
+
+    fn complete(helper: &ReplHelper, line: &str) -> (usize, Vec<String>) {
+        let (start, candidates) = helper.candidates(line, line.len()).unwrap();
+        let replacements = candidates
+            .into_iter()
+            .map(|candidate| candidate.replacement)
+            .collect();
+        (start, replacements)
+    }
+
+    #[test]
+    fn test_complete_words() {
+        let mut helper = ReplHelper::new();
+        helper.set_words(vec![
+            "dup".to_string(),
+            "double".to_string(),
+            "exch".to_string(),
+        ]);
+
+        assert_eq!(complete(&helper, "3 do"), (2, vec!["double".to_string()]));
+        assert_eq!(
+            complete(&helper, "{ d"),
+            (2, vec!["dup".to_string(), "double".to_string()])
+        );
+        assert_eq!(complete(&helper, "/ex"), (1, vec!["exch".to_string()]));
+        assert_eq!(complete(&helper, "3 x").1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_complete_commands_and_paths() {
+        let helper = ReplHelper::new();
+
+        assert_eq!(complete(&helper, ":lo"), (1, vec!["load".to_string()]));
+        assert_eq!(
+            complete(&helper, ":load scripts/lib/ma"),
+            (6, vec!["scripts/lib/math.txt".to_string()])
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use refactoring_rustack::{
+    Breakpoints, DebugCommand, DebugHandler, DebugState, Element, PauseReason,
+};
+
+const HELP: &str = "\
+commands:
+  s, step               stop at the next element
+  n, next               stop at the next element, stepping over word calls
+  f, finish             run until the current word returns
+  c, continue           run until the next breakpoint
+  b, break [word|line]  set a breakpoint (list breakpoints without argument)
+  d, delete <word|line> delete a breakpoint
+  stack                 print the operand stack
+  vars                  print the defined variables
+  bt                    print the words being called
+  q, quit               abort the program
+  h, help               print this help
+  (empty line)          repeat the previous command";
+
+/// 標準入出力で操作するデバッガのハンドラ
+pub struct DebugConsole<R, W> {
+    file: Option<PathBuf>,
+    source: Vec<String>,
+    input: R,
+    output: W,
+    last_command: String,
+}
+
+impl<R: BufRead, W: Write> DebugConsole<R, W> {
+    /// ハンドラを生成する (`file` は `source` を読み込んだファイルで、行のブレークポイントはこのファイルに設定する)
+    pub fn new(file: Option<PathBuf>, source: Vec<String>, input: R, output: W) -> Self {
+        Self {
+            file,
+            source,
+            input,
+            output,
+            last_command: String::from("step"),
+        }
+    }
+
+    /// 一時停止した位置を表示する
+    fn show_location(&mut self, state: &DebugState) -> std::io::Result<()> {
+        if state.reason() == PauseReason::Breakpoint {
+            writeln!(self.output, "breakpoint")?;
+        }
+
+        let line = state.line();
+        if state.file() == self.file.as_deref() {
+            let source = line
+                .checked_sub(1)
+                .and_then(|index| self.source.get(index))
+                .map_or("", |text| text.trim());
+            writeln!(self.output, "{line:>4} | {source}")?;
+        } else {
+            let file = state
+                .file()
+                .map_or(String::from("<input>"), |file| file.display().to_string());
+            writeln!(self.output, "{file}:{line}")?;
+        }
+        writeln!(self.output, "     > {}", state.next_element())
+    }
+
+    /// オペランドスタックを表示する
+    fn show_stack(&mut self, state: &DebugState) -> std::io::Result<()> {
+        let elements: Vec<String> = state
+            .operand_stack()
+            .iter()
+            .map(|element| element.to_string())
+            .collect();
+        writeln!(self.output, "stack: [{}]", elements.join(", "))
+    }
+
+    /// 組み込みの演算以外の変数を内側の辞書から順に表示する
+    fn show_variables(&mut self, state: &DebugState) -> std::io::Result<()> {
+        for (depth, dictionary) in state.dictionaries().iter().enumerate().rev() {
+            let mut variables: Vec<_> = dictionary
+                .iter()
+                .filter(|(_, element)| !matches!(element, Element::NativeOperation(_)))
+                .collect();
+            variables.sort_by_key(|(name, _)| *name);
+
+            for (name, element) in variables {
+                writeln!(self.output, "#{depth} {name} = {element}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 呼び出し中の語を内側から順に表示する
+    fn show_backtrace(&mut self, state: &DebugState) -> std::io::Result<()> {
+        for (depth, name) in state.call_stack().iter().enumerate().rev() {
+            writeln!(self.output, "#{depth} {name}")?;
+        }
+        writeln!(self.output, "#- (top level)")
+    }
+
+    /// ブレークポイントを設定または削除する
+    fn update_breakpoint(
+        &mut self,
+        breakpoints: &mut Breakpoints,
+        target: Option<&str>,
+        add: bool,
+    ) -> std::io::Result<()> {
+        let Some(target) = target else {
+            let mut words: Vec<_> = breakpoints.words().cloned().collect();
+            let mut lines: Vec<_> = breakpoints
+                .lines()
+                .filter(|(file, _)| *file == self.file.as_deref())
+                .map(|(_, line)| line)
+                .collect();
+            words.sort();
+            lines.sort();
+            for word in words {
+                writeln!(self.output, "break {word}")?;
+            }
+            for line in lines {
+                writeln!(self.output, "break {line}")?;
+            }
+            return Ok(());
+        };
+
+        match (target.parse::<usize>(), add) {
+            (Ok(line), true) => breakpoints.add_line(self.file.as_deref(), line),
+            (Ok(line), false) => {
+                breakpoints.remove_line(self.file.as_deref(), line);
+            }
+            (Err(_), true) => breakpoints.add_word(target),
+            (Err(_), false) => {
+                breakpoints.remove_word(target);
+            }
+        }
+        Ok(())
+    }
+
+    /// コマンドを読み込んで実行方法が決まるまで処理する
+    fn prompt(
+        &mut self,
+        state: &DebugState,
+        breakpoints: &mut Breakpoints,
+    ) -> std::io::Result<DebugCommand> {
+        self.show_location(state)?;
+
+        loop {
+            write!(self.output, "(debug) ")?;
+            self.output.flush()?;
+
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                writeln!(self.output)?;
+                return Ok(DebugCommand::Continue);
+            }
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            let mut words = line.split_whitespace();
+            match (words.next().unwrap_or(""), words.next()) {
+                ("s" | "step", _) => return Ok(DebugCommand::Step),
+                ("n" | "next", _) => return Ok(DebugCommand::Next),
+                ("f" | "finish", _) => return Ok(DebugCommand::Finish),
+                ("c" | "continue", _) => return Ok(DebugCommand::Continue),
+                ("q" | "quit", _) => return Ok(DebugCommand::Abort),
+                ("b" | "break", target) => self.update_breakpoint(breakpoints, target, true)?,
+                ("d" | "delete", target) => self.update_breakpoint(breakpoints, target, false)?,
+                ("stack", _) => self.show_stack(state)?,
+                ("vars", _) => self.show_variables(state)?,
+                ("bt", _) => self.show_backtrace(state)?,
+                ("h" | "help", _) => writeln!(self.output, "{HELP}")?,
+                (command, _) => writeln!(self.output, "unknown command: {command} (see help)")?,
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> DebugHandler for DebugConsole<R, W> {
+    fn pause(&mut self, state: &DebugState, breakpoints: &mut Breakpoints) -> DebugCommand {
+        self.prompt(state, breakpoints)
+            .unwrap_or(DebugCommand::Abort)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use refactoring_rustack::{Debugger, OutputBuffer, RuntimeError, VirtualMachine};
+
+    use super::DebugConsole;
+
+    fn run(source: &str, commands: &'static str) -> (String, Result<(), RuntimeError>) {
+        let output = OutputBuffer::new();
+        let lines: Vec<String> = source.lines().map(str::to_string).collect();
+        let console = DebugConsole::new(None, lines, commands.as_bytes(), output.clone());
+
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_debugger(Some(Debugger::new(console)));
+        let result = virtual_machine.eval_str(source);
+
+        (output.text(), result)
+    }
+
+    #[test]
+    fn test_breakpoint_and_inspect() {
+        let source = "/double {\n  2 *\n} def\n10 double";
+        let (output, result) = run(source, "break *\ncontinue\nbt\nstack\nvars\ncontinue\n");
+
+        assert_eq!(result, Ok(()));
+        assert!(output.contains("breakpoint\n   2 | 2 *\n     > *\n"));
+        assert!(output.contains("#0 double\n#- (top level)\n"));
+        assert!(output.contains("stack: [10, 2]\n"));
+        assert!(output.contains("#0 double = { 2 * }\n"));
+    }
+
+    #[test]
+    fn test_repeat_and_quit() {
+        let (output, result) = run("1 2 +", "step\n\nquit\n");
+
+        assert_eq!(result, Err(RuntimeError::Interrupted));
+        assert!(output.contains("     > +\n"));
+    }
+
+    #[test]
+    fn test_location_in_imported_file() {
+        let directory =
+            std::env::temp_dir().join(format!("rustack-debug-console-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let main = directory.join("main.txt");
+        let lib = directory.join("lib.txt");
+        let source = "\"lib.txt\" import\n1 pop\n2 pop";
+        std::fs::write(&main, source).unwrap();
+        std::fs::write(&lib, "5\n6 *\npop").unwrap();
+
+        let output = OutputBuffer::new();
+        let lines: Vec<String> = source.lines().map(str::to_string).collect();
+        let commands = "break *\nbreak 2\ncontinue\ncontinue\n".as_bytes();
+        let console = DebugConsole::new(Some(main.clone()), lines, commands, output.clone());
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_debugger(Some(Debugger::new(console)));
+        let result = virtual_machine.run_file(&main);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        // 読み込んだファイルの2行目では停止せず、元のファイルのソースコードも表示しない
+        let text = output.text();
+        assert_eq!(result, Ok(()));
+        assert!(text.contains(&format!("breakpoint\n{}:2\n     > *\n", lib.display())));
+        assert!(text.contains("breakpoint\n   2 | 1 pop\n     > 1\n"));
+        assert_eq!(text.matches("breakpoint").count(), 2);
+    }
+}
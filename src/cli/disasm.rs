@@ -0,0 +1,67 @@
+use std::io::Write;
+
+use refactoring_rustack::{Element, Parser};
+
+/// ソースコードをパースした要素を行番号と種類とともに書き出す (ブロックの中身は字下げする)
+pub fn disassemble(source: &str, output: &mut impl Write) -> std::io::Result<()> {
+    let mut parser = Parser::new();
+    for line in source.lines() {
+        for element in parser.parse(line.to_string()) {
+            let line = match &element {
+                Element::Block(block) => block.line(),
+                _ => parser.line(),
+            };
+            write_element(&element, line, 0, output)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_element(
+    element: &Element,
+    line: usize,
+    depth: usize,
+    output: &mut impl Write,
+) -> std::io::Result<()> {
+    let indent = "  ".repeat(depth);
+    match element {
+        Element::Block(block) => {
+            writeln!(
+                output,
+                "{line:>4}  {indent}block ({} elements)",
+                block.len()
+            )?;
+            block
+                .iter()
+                .try_for_each(|(element, line)| write_element(element, line, depth + 1, output))
+        }
+        _ => writeln!(
+            output,
+            "{line:>4}  {indent}{} {element}",
+            element.type_name()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+
+    #[test]
+    fn test_disassemble() {
+        let mut output = vec![];
+        disassemble("/f {\n  2 *\n} def\n1.5 f \"a\"", &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "   1  symbol /f\n\
+            \x20  1  block (2 elements)\n\
+            \x20  2    integer 2\n\
+            \x20  2    operation *\n\
+            \x20  3  operation def\n\
+            \x20  4  number 1.5\n\
+            \x20  4  operation f\n\
+            \x20  4  string \"a\"\n"
+        );
+    }
+}
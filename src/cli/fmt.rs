@@ -0,0 +1,79 @@
+use refactoring_rustack::split_line;
+
+/// 字下げの幅
+const INDENT: &str = "    ";
+
+/// ソースコードを整形する
+///
+/// 語の間の空白を1つにし、各行をブロックの深さで字下げする。コメントは行の末尾に残す。
+/// 連続する空行は1行にまとめ、ファイルの先頭と末尾の空行は取り除く。
+/// 整形したソースコードをもう一度整形しても変わらない。
+pub fn format_source(source: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    let mut depth = 0usize;
+    for line in source.lines() {
+        let (words, comment) = split_line(line);
+        if words.is_empty() && comment.is_none() {
+            if lines.last().is_some_and(|last| !last.is_empty()) {
+                lines.push(String::new());
+            }
+            continue;
+        }
+
+        // 行頭の `}` は閉じるブロックの深さで字下げする
+        let leading = words.iter().take_while(|word| *word == "}").count();
+        let indent = depth.saturating_sub(leading);
+        let mut formatted = INDENT.repeat(indent) + &words.join(" ");
+        if let Some(comment) = comment {
+            if !words.is_empty() {
+                formatted.push(' ');
+            }
+            formatted.push_str(comment);
+        }
+        lines.push(formatted);
+
+        let opened = words.iter().filter(|word| *word == "{").count();
+        let closed = words.iter().filter(|word| *word == "}").count();
+        depth = (depth + opened).saturating_sub(closed);
+    }
+    if lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+
+    lines.into_iter().map(|line| line + "\n").collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_source;
+
+    #[test]
+    fn test_format() {
+        let source = "\n/f   {\n        dup   {\n 1 }\n  if\n    }  def\n\n\n\n\"a  b\" puts\n\n";
+        let formatted = "/f {\n    dup {\n        1 }\n    if\n} def\n\n\"a  b\" puts\n";
+
+        assert_eq!(format_source(source), formatted);
+        assert_eq!(format_source(formatted), formatted);
+    }
+
+    #[test]
+    fn test_comment() {
+        let source = "% square\n/sq {\n% body\n  dup    * % twice  \n  }   def\n\"100%\"   %  done";
+        let formatted = "% square\n/sq {\n    % body\n    dup * % twice\n} def\n\"100%\" %  done\n";
+
+        assert_eq!(format_source(source), formatted);
+        assert_eq!(format_source(formatted), formatted);
+    }
+
+    #[test]
+    fn test_idempotent() {
+        for entry in std::fs::read_dir("scripts").unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                continue;
+            }
+            let formatted = format_source(&std::fs::read_to_string(&path).unwrap());
+            assert_eq!(format_source(&formatted), formatted, "{}", path.display());
+        }
+    }
+}
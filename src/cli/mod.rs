@@ -0,0 +1,14 @@
+mod check;
+mod completion;
+mod debug_console;
+mod disasm;
+mod fmt;
+mod options;
+mod repl;
+
+pub use self::check::*;
+pub use self::debug_console::*;
+pub use self::disasm::*;
+pub use self::fmt::*;
+pub use self::options::*;
+pub use self::repl::*;
@@ -0,0 +1,264 @@
+use std::path::PathBuf;
+
+use refactoring_rustack::TraceFormat;
+
+pub const USAGE: &str = "\
+usage: refactoring-rustack [OPTIONS] [COMMAND] [-- ARGS...]
+
+commands:
+  run FILE...       run files in order (the default when files are given, - reads stdin)
+  -e CODE           evaluate code
+  repl              start the interactive mode (the default without files on a terminal)
+  check FILE...     check files for syntax errors
+  fmt FILE...       format files in place (--check reports unformatted files)
+  disasm FILE...    print the parsed elements of files
+
+options:
+  --path=DIR            add a directory to search for imported files
+  --bare                start without the standard words
+  --debug               run with the debugger
+  --trace[=json]        trace evaluated elements to stderr
+  --profile             print a profile to stderr
+  --profile-folded=FILE write a profile in folded stack format
+  -h, --help            print this help
+
+arguments after -- are passed to the program";
+
+/// 標準入力を表すファイル名
+pub const STDIN: &str = "-";
+
+/// 実行するコマンド
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// ファイルを順に実行する
+    Run(Vec<String>),
+    /// コマンドラインで渡したコードを評価する
+    Eval(String),
+    /// 対話モードを開始する
+    Repl,
+    /// ファイルの構文を確認する
+    Check(Vec<String>),
+    /// ファイルを整形する (`check` の場合は書き換えずに整形されていないファイルを報告する)
+    Fmt { files: Vec<String>, check: bool },
+    /// ファイルをパースした要素を表示する
+    Disasm(Vec<String>),
+    /// 使い方を表示する
+    Help,
+}
+
+/// コマンドライン引数
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    pub search_path: Vec<PathBuf>,
+    pub bare: bool,
+    pub debug: bool,
+    pub trace: Option<TraceFormat>,
+    pub profile: bool,
+    pub folded: Option<String>,
+    /// `--` の後ろのプログラムに渡す引数
+    pub args: Vec<String>,
+}
+
+impl Options {
+    /// コマンドライン引数 (プログラム名を除く) を解釈する
+    ///
+    /// `interactive` は標準入力が端末かどうかで、端末でなくファイルも指定されていなければ標準入力のプログラムを実行する。
+    pub fn parse(
+        args: impl IntoIterator<Item = String>,
+        interactive: bool,
+    ) -> Result<Self, String> {
+        let mut options = Options {
+            command: Command::Repl,
+            search_path: vec![],
+            bare: false,
+            debug: false,
+            trace: None,
+            profile: false,
+            folded: None,
+            args: vec![],
+        };
+        let mut code = None;
+        let mut check = false;
+        let mut positionals = vec![];
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--" => {
+                    options.args = args.by_ref().collect();
+                }
+                "-h" | "--help" => {
+                    return Ok(Options {
+                        command: Command::Help,
+                        ..options
+                    })
+                }
+                "-e" => {
+                    let source = args.next().ok_or("-e requires code")?;
+                    code = Some(source);
+                }
+                "--bare" => options.bare = true,
+                "--debug" => options.debug = true,
+                "--trace" => options.trace = Some(TraceFormat::Text),
+                "--trace=json" => options.trace = Some(TraceFormat::JsonLines),
+                "--profile" => options.profile = true,
+                "--check" => check = true,
+                _ => {
+                    if let Some(path) = arg.strip_prefix("--path=") {
+                        options.search_path.push(PathBuf::from(path));
+                    } else if let Some(path) = arg.strip_prefix("--profile-folded=") {
+                        options.folded = Some(path.to_string());
+                    } else if arg.starts_with('-') && arg.len() > 1 {
+                        return Err(format!("unknown option {arg}"));
+                    } else {
+                        positionals.push(arg);
+                    }
+                }
+            }
+        }
+
+        options.command = match (code, positionals.first().map(String::as_str)) {
+            (Some(code), None) => Command::Eval(code),
+            (Some(_), Some(arg)) => return Err(format!("unexpected argument {arg} after -e")),
+            (None, None) if interactive => Command::Repl,
+            (None, None) => Command::Run(vec![STDIN.to_string()]),
+            (None, Some("repl")) if positionals.len() == 1 => Command::Repl,
+            (None, Some("repl")) => return Err("repl takes no files".to_string()),
+            (None, Some(name @ ("run" | "check" | "fmt" | "disasm"))) => {
+                let files = positionals[1..].to_vec();
+                if files.is_empty() {
+                    return Err(format!("{name} requires at least one file"));
+                }
+                match name {
+                    "run" => Command::Run(files),
+                    "check" => Command::Check(files),
+                    "fmt" => Command::Fmt { files, check },
+                    _ => Command::Disasm(files),
+                }
+            }
+            (None, Some(_)) => Command::Run(positionals),
+        };
+        if check && !matches!(options.command, Command::Fmt { .. }) {
+            return Err("--check is only valid with fmt".to_string());
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use refactoring_rustack::TraceFormat;
+
+    use super::{Command, Options};
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()), true)
+    }
+
+    fn files(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(parse(&[]).unwrap().command, Command::Repl);
+        assert_eq!(parse(&["repl"]).unwrap().command, Command::Repl);
+        assert_eq!(
+            parse(&["a.txt", "b.txt"]).unwrap().command,
+            Command::Run(files(&["a.txt", "b.txt"]))
+        );
+        assert_eq!(
+            parse(&["run", "a.txt"]).unwrap().command,
+            Command::Run(files(&["a.txt"]))
+        );
+        assert_eq!(
+            parse(&["-e", "1 2 + puts"]).unwrap().command,
+            Command::Eval("1 2 + puts".to_string())
+        );
+        assert_eq!(
+            parse(&["check", "a.txt"]).unwrap().command,
+            Command::Check(files(&["a.txt"]))
+        );
+        assert_eq!(
+            parse(&["fmt", "a.txt"]).unwrap().command,
+            Command::Fmt {
+                files: files(&["a.txt"]),
+                check: false
+            }
+        );
+        assert_eq!(
+            parse(&["fmt", "--check", "a.txt"]).unwrap().command,
+            Command::Fmt {
+                files: files(&["a.txt"]),
+                check: true
+            }
+        );
+        assert_eq!(
+            parse(&["disasm", "a.txt"]).unwrap().command,
+            Command::Disasm(files(&["a.txt"]))
+        );
+        assert_eq!(parse(&["--help", "run"]).unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn test_stdin() {
+        let batch = |args: &[&str]| {
+            Options::parse(args.iter().map(|arg| arg.to_string()), false)
+                .unwrap()
+                .command
+        };
+
+        assert_eq!(batch(&[]), Command::Run(files(&["-"])));
+        assert_eq!(batch(&["--", "a"]), Command::Run(files(&["-"])));
+        assert_eq!(batch(&["repl"]), Command::Repl);
+        assert_eq!(batch(&["a.txt"]), Command::Run(files(&["a.txt"])));
+        assert_eq!(parse(&["-"]).unwrap().command, Command::Run(files(&["-"])));
+    }
+
+    #[test]
+    fn test_options() {
+        let options = parse(&[
+            "--bare",
+            "--path=lib",
+            "run",
+            "--trace=json",
+            "a.txt",
+            "--",
+            "-x",
+            "b.txt",
+        ])
+        .unwrap();
+
+        assert_eq!(options.command, Command::Run(files(&["a.txt"])));
+        assert!(options.bare);
+        assert_eq!(options.search_path, vec![std::path::PathBuf::from("lib")]);
+        assert_eq!(options.trace, Some(TraceFormat::JsonLines));
+        assert_eq!(options.args, files(&["-x", "b.txt"]));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse(&["--frobnicate"]),
+            Err("unknown option --frobnicate".to_string())
+        );
+        assert_eq!(parse(&["-e"]), Err("-e requires code".to_string()));
+        assert_eq!(
+            parse(&["-e", "1", "a.txt"]),
+            Err("unexpected argument a.txt after -e".to_string())
+        );
+        assert_eq!(
+            parse(&["check"]),
+            Err("check requires at least one file".to_string())
+        );
+        assert_eq!(
+            parse(&["--check", "a.txt"]),
+            Err("--check is only valid with fmt".to_string())
+        );
+        assert_eq!(
+            parse(&["repl", "a.txt"]),
+            Err("repl takes no files".to_string())
+        );
+    }
+}
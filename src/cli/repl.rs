@@ -0,0 +1,358 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use refactoring_rustack::{Element, Parser, RuntimeError, Snapshot, VirtualMachine};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+
+use super::completion::ReplHelper;
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+const HELP: &str = "\
+commands:
+  :load FILE   evaluate a file
+  :reset       restore the state at the start of the session
+  :stack       print the operand stack
+  :words       print the defined words
+  :see WORD    print the definition of a word
+  :time CODE   evaluate code and print the elapsed time
+  :undo        restore the state before the last line
+  :quit        exit the session
+  :help        print this help";
+
+/// `:` から始まるコマンドの名前
+pub const COMMANDS: &[&str] = &[
+    "load", "reset", "stack", "words", "see", "time", "undo", "quit", "help",
+];
+
+/// 1行を処理した後に対話モードを続けるか
+#[derive(Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+    /// `exit` で終了コードを指定して終了する
+    Exit(i32),
+}
+
+/// 対話モードのセッション
+pub struct Repl {
+    virtual_machine: VirtualMachine,
+    parser: Parser,
+    /// セッションを始めたときの状態
+    initial: Snapshot,
+    /// 各行を評価する前の状態
+    undo: Vec<Snapshot>,
+}
+
+impl Repl {
+    /// セッションを生成する
+    pub fn new(virtual_machine: VirtualMachine) -> Self {
+        Self {
+            initial: virtual_machine.snapshot(),
+            virtual_machine,
+            parser: Parser::new(),
+            undo: vec![],
+        }
+    }
+
+    /// 次の行を読み込むときのプロンプトを返す (ブロックが閉じていなければ継続行のプロンプトにする)
+    pub fn prompt(&self) -> &'static str {
+        if self.parser.is_inside_block() {
+            CONTINUATION_PROMPT
+        } else {
+            PROMPT
+        }
+    }
+
+    /// 閉じていないブロックを破棄する
+    pub fn cancel(&mut self) {
+        self.parser = Parser::new();
+    }
+
+    /// 1行を処理し、エラーまたは入力が完結した後のスタックを書き出す
+    ///
+    /// ブロックの外で `:` から始まる行はコマンドとして扱う。
+    pub fn eval(&mut self, line: &str, output: &mut impl Write) -> std::io::Result<Flow> {
+        if !self.parser.is_inside_block() {
+            if let Some(command) = line.trim_start().strip_prefix(':') {
+                return self.command(command, output);
+            }
+            self.undo.push(self.virtual_machine.snapshot());
+        }
+
+        if let Err(error) = self.virtual_machine.eval_line(&mut self.parser, line) {
+            self.cancel();
+            return report(error, output);
+        }
+        if !self.parser.is_inside_block() {
+            self.show_stack(output)?;
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// コマンドを実行する
+    fn command(&mut self, command: &str, output: &mut impl Write) -> std::io::Result<Flow> {
+        let (name, argument) = command
+            .trim()
+            .split_once(char::is_whitespace)
+            .map_or((command.trim(), ""), |(name, argument)| {
+                (name, argument.trim())
+            });
+
+        match (name, argument) {
+            ("load", "") => writeln!(output, "usage: :load FILE")?,
+            ("load", path) => {
+                self.undo.push(self.virtual_machine.snapshot());
+                match self.virtual_machine.run_file(path) {
+                    Ok(()) => self.show_stack(output)?,
+                    Err(error) => return report(error, output),
+                }
+            }
+            ("reset", _) => {
+                self.virtual_machine.restore(self.initial.clone());
+                self.undo.clear();
+                self.show_stack(output)?;
+            }
+            ("stack", _) => self.show_stack(output)?,
+            ("words", _) => writeln!(output, "{}", self.words().join(" "))?,
+            ("see", "") => writeln!(output, "usage: :see WORD")?,
+            ("see", word) => match self.virtual_machine.stack().lookup(word) {
+                Ok(Element::NativeOperation(native)) => match native.signature() {
+                    Some(signature) => writeln!(output, "{word} {signature} <native>")?,
+                    None => writeln!(output, "{word} <native>")?,
+                },
+                Ok(element) => writeln!(output, "/{word} {element} def")?,
+                Err(error) => writeln!(output, "error: {error}")?,
+            },
+            ("time", "") => writeln!(output, "usage: :time CODE")?,
+            ("time", code) => {
+                self.undo.push(self.virtual_machine.snapshot());
+                let steps = self.virtual_machine.steps();
+                let start = Instant::now();
+                let result = self.virtual_machine.eval_str(code);
+                let elapsed = start.elapsed();
+                let steps = self.virtual_machine.steps() - steps;
+                match result {
+                    Ok(()) => {
+                        writeln!(output, "time: {elapsed:?} ({steps} steps)")?;
+                        self.show_stack(output)?;
+                    }
+                    Err(error) => return report(error, output),
+                }
+            }
+            ("undo", _) => match self.undo.pop() {
+                Some(snapshot) => {
+                    self.virtual_machine.restore(snapshot);
+                    self.show_stack(output)?;
+                }
+                None => writeln!(output, "nothing to undo")?,
+            },
+            ("quit" | "q", _) => return Ok(Flow::Quit),
+            ("help" | "h", _) => writeln!(output, "{HELP}")?,
+            (name, _) => writeln!(output, "unknown command :{name} (type :help for commands)")?,
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// 辞書スタックで定義された語の名前を重複なしに名前順で返す
+    pub fn words(&self) -> Vec<String> {
+        let words: BTreeSet<&String> = self
+            .virtual_machine
+            .stack()
+            .dictionaries()
+            .iter()
+            .flat_map(|dictionary| dictionary.keys())
+            .collect();
+        words.into_iter().cloned().collect()
+    }
+
+    /// オペランドスタックを表示する
+    fn show_stack(&self, output: &mut impl Write) -> std::io::Result<()> {
+        let elements: Vec<String> = self
+            .virtual_machine
+            .stack()
+            .list()
+            .iter()
+            .map(|element| element.to_string())
+            .collect();
+        writeln!(output, "stack: [{}]", elements.join(", "))
+    }
+}
+
+/// エラーを表示する (`exit` の場合はセッションを終了する)
+fn report(error: RuntimeError, output: &mut impl Write) -> std::io::Result<Flow> {
+    if let RuntimeError::Exit(status) = error {
+        return Ok(Flow::Exit(status));
+    }
+    writeln!(output, "error: {error}")?;
+    Ok(Flow::Continue)
+}
+
+/// 行の読み込みのエラーを実行時エラーにする
+fn readline_error(error: ReadlineError) -> RuntimeError {
+    RuntimeError::Io(error.to_string())
+}
+
+/// 履歴を保存するファイルのパスを返す
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rustack_history"))
+}
+
+/// 行編集と履歴を備えた対話モードを実行する (`exit` の場合は [`RuntimeError::Exit`] を返す)
+pub fn run_repl(virtual_machine: VirtualMachine) -> Result<(), RuntimeError> {
+    // Ctrl-C では現在の行の実行だけを中断し、セッションは継続する
+    let interrupt = virtual_machine.interrupt_handle();
+    let handler = interrupt.clone();
+    if let Err(error) = ctrlc::set_handler(move || handler.interrupt()) {
+        eprintln!("warning: failed to set Ctrl-C handler: {error}");
+    }
+
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new().map_err(readline_error)?;
+    editor.set_helper(Some(ReplHelper::new()));
+    let history = history_path();
+    if let Some(path) = &history {
+        // 初回は履歴のファイルがないので読み込みの失敗は無視する
+        let _ = editor.load_history(path);
+    }
+
+    let mut repl = Repl::new(virtual_machine);
+    let result = loop {
+        // セッションで定義した語も補完できるように、読み込むたびに語を更新する
+        if let Some(helper) = editor.helper_mut() {
+            helper.set_words(repl.words());
+        }
+        match editor.readline(repl.prompt()) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    editor
+                        .add_history_entry(line.as_str())
+                        .map_err(readline_error)?;
+                }
+                interrupt.clear();
+                let flow = repl
+                    .eval(&line, &mut std::io::stdout())
+                    .map_err(|error| RuntimeError::Io(error.to_string()))?;
+                match flow {
+                    Flow::Continue => {}
+                    Flow::Quit => break Ok(()),
+                    Flow::Exit(status) => break Err(RuntimeError::Exit(status)),
+                }
+            }
+            Err(ReadlineError::Interrupted) => repl.cancel(),
+            Err(ReadlineError::Eof) => break Ok(()),
+            Err(error) => return Err(readline_error(error)),
+        }
+    };
+
+    if let Some(path) = &history {
+        if let Err(error) = editor.save_history(path) {
+            eprintln!(
+                "warning: failed to save history to {}: {error}",
+                path.display()
+            );
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use refactoring_rustack::VirtualMachine;
+
+    use super::{Flow, Repl, CONTINUATION_PROMPT, PROMPT};
+
+    fn eval(repl: &mut Repl, line: &str) -> String {
+        let mut output = vec![];
+        assert_eq!(repl.eval(line, &mut output).unwrap(), Flow::Continue);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_continuation() {
+        let mut repl = Repl::new(VirtualMachine::new());
+
+        assert_eq!(eval(&mut repl, "/f {"), "");
+        assert_eq!(repl.prompt(), CONTINUATION_PROMPT);
+        assert_eq!(eval(&mut repl, "  2 *"), "");
+        assert_eq!(eval(&mut repl, "} def"), "stack: []\n");
+        assert_eq!(repl.prompt(), PROMPT);
+        assert_eq!(
+            eval(&mut repl, "1.5 f \"a\" { 1 }"),
+            "stack: [3.0, \"a\", { 1 }]\n"
+        );
+    }
+
+    #[test]
+    fn test_error_keeps_session() {
+        let mut repl = Repl::new(VirtualMachine::new());
+
+        assert_eq!(
+            eval(&mut repl, "1 undefined 2"),
+            "error: \"undefined\" is undefined\n"
+        );
+        assert_eq!(eval(&mut repl, "3"), "stack: [1, 3]\n");
+
+        eval(&mut repl, "{ 1");
+        repl.cancel();
+        assert_eq!(repl.prompt(), PROMPT);
+        assert_eq!(eval(&mut repl, "pop"), "stack: [1]\n");
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut repl = Repl::new(VirtualMachine::new());
+
+        assert_eq!(eval(&mut repl, "1 /inc { 1 + } def"), "stack: [1]\n");
+        assert_eq!(eval(&mut repl, ":see inc"), "/inc { 1 + } def\n");
+        assert_eq!(
+            eval(&mut repl, ":see +"),
+            "+ ( lhs:number rhs:number -- number ) <native>\n"
+        );
+        assert!(eval(&mut repl, ":words").contains(" inc "));
+        assert!(eval(&mut repl, ":time 2 inc").ends_with("steps)\nstack: [1, 3.0]\n"));
+        assert_eq!(eval(&mut repl, ":undo"), "stack: [1]\n");
+        assert_eq!(
+            eval(&mut repl, ":load scripts/lib/math.txt"),
+            "stack: [1]\n"
+        );
+        assert_eq!(eval(&mut repl, "double"), "stack: [2.0]\n");
+        assert_eq!(eval(&mut repl, ":stack"), "stack: [2.0]\n");
+        assert_eq!(eval(&mut repl, ":reset"), "stack: []\n");
+        assert_eq!(eval(&mut repl, ":see inc"), "error: \"inc\" is undefined\n");
+        assert_eq!(eval(&mut repl, ":undo"), "nothing to undo\n");
+        assert_eq!(
+            eval(&mut repl, ":frobnicate"),
+            "unknown command :frobnicate (type :help for commands)\n"
+        );
+
+        let mut output = vec![];
+        assert_eq!(repl.eval(":quit", &mut output).unwrap(), Flow::Quit);
+        assert_eq!(repl.eval("1 3 exit", &mut output).unwrap(), Flow::Exit(3));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_undo_import() {
+        let mut repl = Repl::new(VirtualMachine::new());
+
+        assert_eq!(
+            eval(&mut repl, "\"scripts/lib/math.txt\" import"),
+            "stack: []\n"
+        );
+        assert_eq!(eval(&mut repl, ":undo"), "stack: []\n");
+        assert_eq!(
+            eval(&mut repl, "\"scripts/lib/math.txt\" import 3 double"),
+            "stack: [6.0]\n"
+        );
+        assert_eq!(eval(&mut repl, ":reset"), "stack: []\n");
+        assert_eq!(
+            eval(&mut repl, "\"scripts/lib/math.txt\" import 3 square"),
+            "stack: [9.0]\n"
+        );
+    }
+}
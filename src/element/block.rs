@@ -1,26 +1,39 @@
-use std::{cell::RefMut, vec::IntoIter};
+use std::{cell::RefMut, fmt, vec::IntoIter};
 
 use super::Element;
 
 /// ブロック要素を表す構造体
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub struct Block {
     tokens: Vec<Element>,
+    /// 各要素のソースコード上の行番号 (不明な場合は0)
+    lines: Vec<usize>,
+    /// ブロックが始まる行番号 (不明な場合は0)
+    line: usize,
 }
 
 impl Block {
-    fn new() -> Self {
-        Self { tokens: vec![] }
+    fn new(line: usize) -> Self {
+        Self {
+            tokens: vec![],
+            lines: vec![],
+            line,
+        }
     }
 
-    fn add(&mut self, element: Element) {
+    fn add(&mut self, element: Element, line: usize) {
         self.tokens.push(element);
+        self.lines.push(line);
     }
 
     /// パースする
-    pub fn parse(iter: &mut IntoIter<String>, blocks: &mut RefMut<Vec<Block>>) -> Option<Block> {
+    pub fn parse(
+        iter: &mut IntoIter<String>,
+        blocks: &mut RefMut<Vec<Block>>,
+        line: usize,
+    ) -> Option<Block> {
         if blocks.is_empty() {
-            blocks.push(Block::new());
+            blocks.push(Block::new(line));
         }
 
         let index = blocks.len() - 1;
@@ -29,38 +42,96 @@ impl Block {
             if word.is_empty() {
                 continue;
             } else if word == "{" {
-                blocks.push(Block::new());
-                let inner_block = Block::parse(iter, blocks)?;
+                blocks.push(Block::new(line));
+                let inner_block = Block::parse(iter, blocks, line)?;
                 if blocks.is_empty() {
                     return Some(inner_block);
                 } else {
-                    blocks[index].add(Element::Block(inner_block));
+                    let inner_line = inner_block.line;
+                    blocks[index].add(Element::Block(inner_block), inner_line);
                 }
             } else if word == "}" {
                 let block = blocks.pop().unwrap();
                 if blocks.is_empty() {
                     return Some(block);
                 } else {
-                    blocks[index - 1].add(Element::Block(block));
-                    return Block::parse(iter, blocks);
+                    let block_line = block.line;
+                    blocks[index - 1].add(Element::Block(block), block_line);
+                    return Block::parse(iter, blocks, line);
                 }
-            } else if let Ok(parsed) = word.parse::<i32>() {
-                blocks[index].add(Element::Integer(parsed))
-            } else if let Ok(parsed) = word.parse::<f32>() {
-                blocks[index].add(Element::Number(parsed))
-            } else if word.starts_with("/") && word.len() >= 2 {
-                blocks[index].add(Element::Symbol(word[1..].to_owned()))
             } else {
-                blocks[index].add(Element::Operation(word.to_string()))
+                blocks[index].add(Element::from_word(word), line)
             }
         }
 
         None
     }
 
+    /// 要素からブロックを生成する (行番号は不明とする)
+    pub fn from_vec(tokens: Vec<Element>) -> Self {
+        let lines = vec![0; tokens.len()];
+        Self {
+            tokens,
+            lines,
+            line: 0,
+        }
+    }
+
     pub fn to_vec(&self) -> Vec<Element> {
         self.tokens.clone()
     }
+
+    /// 要素とその行番号を順に返す
+    pub fn iter(&self) -> impl Iterator<Item = (&Element, usize)> {
+        self.tokens.iter().zip(self.lines.iter().copied())
+    }
+
+    /// ブロックが始まる行番号を返す (不明な場合は0)
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 直下の要素の数を返す
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// 要素がないか
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// 直下のn番目 (先頭は0) の要素を返す
+    pub fn get(&self, index: usize) -> Option<&Element> {
+        self.tokens.get(index)
+    }
+
+    /// ブロックの中身を含めた要素の数を返す
+    pub fn count(&self) -> usize {
+        self.tokens.iter().map(Element::count).sum()
+    }
+
+    /// 中身の要素のおおよそのバイト数を返す
+    pub fn size(&self) -> usize {
+        self.tokens.iter().map(Element::size).sum()
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for token in &self.tokens {
+            write!(f, " {token}")?;
+        }
+        write!(f, " }}")
+    }
+}
+
+impl PartialEq for Block {
+    /// 行番号は比較せず、中身の要素だけを比較する
+    fn eq(&self, other: &Self) -> bool {
+        self.tokens == other.tokens
+    }
 }
 
 #[cfg(test)]
@@ -70,7 +141,12 @@ pub mod tests {
     use super::{Block, Element};
 
     pub fn create_block(tokens: Vec<Element>) -> Block {
-        Block { tokens }
+        let lines = vec![0; tokens.len()];
+        Block {
+            tokens,
+            lines,
+            line: 0,
+        }
     }
 
     #[test]
@@ -127,4 +203,21 @@ pub mod tests {
             )]))]
         );
     }
+
+    #[test]
+    fn test_lines() {
+        let mut parser = Parser::new();
+        let mut actual = vec![];
+        for line in ["/f {", "  1", "  { 2 }", "} def"] {
+            actual.extend(parser.parse(String::from(line)));
+        }
+
+        let Element::Block(block) = &actual[1] else {
+            panic!("not a block: {:?}", actual[1]);
+        };
+        let lines: Vec<usize> = block.iter().map(|(_, line)| line).collect();
+
+        assert_eq!(block.line(), 1);
+        assert_eq!(lines, vec![2, 3]);
+    }
 }
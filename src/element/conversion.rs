@@ -0,0 +1,156 @@
+use crate::virtual_machine::RuntimeError;
+
+use super::{Block, Element};
+
+impl From<i32> for Element {
+    fn from(value: i32) -> Self {
+        Element::Integer(value)
+    }
+}
+
+impl From<f32> for Element {
+    fn from(value: f32) -> Self {
+        Element::Number(value)
+    }
+}
+
+impl From<f64> for Element {
+    fn from(value: f64) -> Self {
+        Element::Number(value as f32)
+    }
+}
+
+impl From<bool> for Element {
+    /// 真は1、偽は0の整数にする
+    fn from(value: bool) -> Self {
+        Element::Integer(value as i32)
+    }
+}
+
+impl From<String> for Element {
+    fn from(value: String) -> Self {
+        Element::String(value)
+    }
+}
+
+impl From<&str> for Element {
+    fn from(value: &str) -> Self {
+        Element::String(value.to_string())
+    }
+}
+
+impl From<Block> for Element {
+    fn from(value: Block) -> Self {
+        Element::Block(value)
+    }
+}
+
+impl TryFrom<Element> for i32 {
+    type Error = RuntimeError;
+
+    /// 数値は小数点以下を切り捨てて整数にする
+    fn try_from(element: Element) -> Result<Self, Self::Error> {
+        match element {
+            Element::Integer(num) => Ok(num),
+            Element::Number(num) => Ok(num as i32),
+            found => Err(RuntimeError::TypeMismatch {
+                expected: "integer",
+                found,
+            }),
+        }
+    }
+}
+
+impl TryFrom<Element> for f32 {
+    type Error = RuntimeError;
+
+    /// 整数は数値にする
+    fn try_from(element: Element) -> Result<Self, Self::Error> {
+        match element {
+            Element::Integer(num) => Ok(num as f32),
+            Element::Number(num) => Ok(num),
+            found => Err(RuntimeError::TypeMismatch {
+                expected: "number",
+                found,
+            }),
+        }
+    }
+}
+
+impl TryFrom<Element> for f64 {
+    type Error = RuntimeError;
+
+    fn try_from(element: Element) -> Result<Self, Self::Error> {
+        f32::try_from(element).map(f64::from)
+    }
+}
+
+impl TryFrom<Element> for bool {
+    type Error = RuntimeError;
+
+    /// 0以外の数値を真とする
+    fn try_from(element: Element) -> Result<Self, Self::Error> {
+        i32::try_from(element).map(|num| num != 0)
+    }
+}
+
+impl TryFrom<Element> for String {
+    type Error = RuntimeError;
+
+    fn try_from(element: Element) -> Result<Self, Self::Error> {
+        match element {
+            Element::String(string) => Ok(string),
+            found => Err(RuntimeError::TypeMismatch {
+                expected: "string",
+                found,
+            }),
+        }
+    }
+}
+
+impl TryFrom<Element> for Block {
+    type Error = RuntimeError;
+
+    fn try_from(element: Element) -> Result<Self, Self::Error> {
+        match element {
+            Element::Block(block) => Ok(block),
+            found => Err(RuntimeError::TypeMismatch {
+                expected: "block",
+                found,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::element::tests::create_block;
+    use crate::element::{Block, Element};
+    use crate::virtual_machine::RuntimeError;
+
+    #[test]
+    fn test_into_element() {
+        assert_eq!(Element::from(3), Element::Integer(3));
+        assert_eq!(Element::from(1.5), Element::Number(1.5));
+        assert_eq!(Element::from(true), Element::Integer(1));
+        assert_eq!(Element::from("abc"), Element::String("abc".to_string()));
+    }
+
+    #[test]
+    fn test_try_from_element() {
+        assert_eq!(i32::try_from(Element::Number(2.7)), Ok(2));
+        assert_eq!(f32::try_from(Element::Integer(2)), Ok(2.0));
+        assert_eq!(bool::try_from(Element::Integer(0)), Ok(false));
+        assert_eq!(
+            Block::try_from(Element::Block(create_block(vec![Element::Integer(1)]))),
+            Ok(create_block(vec![Element::Integer(1)]))
+        );
+        assert_eq!(
+            String::try_from(Element::Integer(1)),
+            Err(RuntimeError::TypeMismatch {
+                expected: "string",
+                found: Element::Integer(1)
+            })
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::{cell::RefCell, rc::Rc, vec::IntoIter};
+use std::{cell::RefCell, fmt, rc::Rc, vec::IntoIter};
 
 use super::{block::Block, native_operation::NativeOperation};
 
@@ -13,6 +13,8 @@ pub enum Element {
     Operation(String),
     /// シンボル
     Symbol(String),
+    /// 文字列
+    String(String),
     /// ブロック
     Block(Block),
     /// 組み込みの演算
@@ -24,28 +26,38 @@ impl Element {
     pub fn parse(
         iter: &mut IntoIter<String>,
         blocks: &mut Rc<RefCell<Vec<Block>>>,
+        line: usize,
     ) -> Option<Element> {
         let mut borrowed = blocks.borrow_mut();
 
         if !borrowed.is_empty() {
-            let block = Block::parse(iter, &mut borrowed)?;
+            let block = Block::parse(iter, &mut borrowed, line)?;
             return Some(Element::Block(block));
         }
 
         let word = iter.next()?;
         if word.is_empty() {
-            return None;
+            None
         } else if word == "{" {
-            let block = Block::parse(iter, &mut borrowed)?;
+            let block = Block::parse(iter, &mut borrowed, line)?;
             Some(Element::Block(block))
-        } else if let Ok(parsed) = word.parse::<i32>() {
-            Some(Element::Integer(parsed))
+        } else {
+            Some(Element::from_word(word))
+        }
+    }
+
+    /// ブロック以外の語をパースする
+    pub(crate) fn from_word(word: String) -> Element {
+        if let Ok(parsed) = word.parse::<i32>() {
+            Element::Integer(parsed)
         } else if let Ok(parsed) = word.parse::<f32>() {
-            Some(Element::Number(parsed))
+            Element::Number(parsed)
         } else if word.starts_with("/") && word.len() >= 2 {
-            Some(Element::Symbol(word[1..].to_owned()))
+            Element::Symbol(word[1..].to_owned())
+        } else if let Some(string) = unescape(&word) {
+            Element::String(string)
         } else {
-            Some(Element::Operation(word.to_string()))
+            Element::Operation(word)
         }
     }
 
@@ -78,6 +90,93 @@ impl Element {
             _ => panic!("Value is not a block"),
         }
     }
+
+    /// 型の名前を返す
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Element::Integer(_) => "integer",
+            Element::Number(_) => "number",
+            Element::Operation(_) => "operation",
+            Element::Symbol(_) => "symbol",
+            Element::String(_) => "string",
+            Element::Block(_) => "block",
+            Element::NativeOperation(_) => "native operation",
+        }
+    }
+
+    /// 出力用の文字列を返す (数値は小数点以下が0なら省略し、文字列は引用符を付けない)
+    pub fn to_text(&self) -> String {
+        match self {
+            Element::Number(num) => num.to_string(),
+            Element::String(string) => string.clone(),
+            _ => self.to_string(),
+        }
+    }
+
+    /// ブロックの中身を含めた要素の数を返す
+    pub fn count(&self) -> usize {
+        match self {
+            Element::Block(block) => 1 + block.count(),
+            _ => 1,
+        }
+    }
+
+    /// ブロックの中身を含めた要素のおおよそのバイト数を返す
+    pub fn size(&self) -> usize {
+        let heap = match self {
+            Element::Operation(name) | Element::Symbol(name) | Element::String(name) => name.len(),
+            Element::Block(block) => block.size(),
+            _ => 0,
+        };
+        std::mem::size_of::<Element>() + heap
+    }
+}
+
+impl fmt::Display for Element {
+    /// ソースコードの形式で表示する
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Element::Integer(num) => write!(f, "{num}"),
+            Element::Number(num) => write!(f, "{num:?}"),
+            Element::Operation(name) => write!(f, "{name}"),
+            Element::Symbol(name) => write!(f, "/{name}"),
+            Element::String(string) => write!(f, "{string:?}"),
+            Element::Block(block) => write!(f, "{block}"),
+            Element::NativeOperation(_) => write!(f, "<native>"),
+        }
+    }
+}
+
+/// `"` で囲まれた文字列リテラルのエスケープを戻す (文字列リテラルでない場合は `None`)
+fn unescape(word: &str) -> Option<String> {
+    let content = word.strip_prefix('"')?.strip_suffix('"')?;
+    let mut string = String::new();
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            if c == '"' {
+                return None;
+            }
+            string.push(c);
+            continue;
+        }
+        let escaped = match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            'u' => {
+                let code = chars.as_str().strip_prefix('{')?.split_once('}')?.0;
+                let escaped = char::from_u32(u32::from_str_radix(code, 16).ok()?)?;
+                chars.nth(code.len() + 1);
+                escaped
+            }
+            c @ ('\\' | '"' | '\'') => c,
+            _ => return None,
+        };
+        string.push(escaped);
+    }
+    Some(string)
 }
 
 #[cfg(test)]
@@ -105,4 +204,53 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_display() {
+        let mut parser = Parser::new();
+        let iter = parser.parse(String::from("/x 1 2.5 { 3 { x } } dup"));
+        let actual: Vec<String> = iter.map(|element| element.to_string()).collect();
+
+        assert_eq!(actual, vec!["/x", "1", "2.5", "{ 3 { x } }", "dup"]);
+    }
+
+    #[test]
+    fn test_string() {
+        let mut parser = Parser::new();
+        let source = r#""hello,  world" { "a\"b\\" } "\t\u{3042}\n" "" "open"#;
+        let actual: Vec<Element> = parser.parse(source.to_string()).collect();
+
+        assert_eq!(
+            actual,
+            vec![
+                Element::String("hello,  world".to_string()),
+                Element::Block(create_block(vec![Element::String("a\"b\\".to_string())])),
+                Element::String("\tあ\n".to_string()),
+                Element::String("".to_string()),
+                Element::Operation("\"open".to_string()),
+            ]
+        );
+        assert_eq!(actual[1].to_string(), r#"{ "a\"b\\" }"#);
+
+        let reparsed: Vec<Element> = parser.parse(actual[2].to_string()).collect();
+        assert_eq!(reparsed, vec![actual[2].clone()]);
+    }
+
+    #[test]
+    fn test_comment() {
+        let mut parser = Parser::new();
+        let source = r#"1 % one "%" {"#;
+        let actual: Vec<Element> = parser.parse(source.to_string()).collect();
+        assert_eq!(actual, vec![Element::Integer(1)]);
+
+        let source = r#"{ "50%" } %"#;
+        let actual: Vec<Element> = parser.parse(source.to_string()).collect();
+        assert_eq!(
+            actual,
+            vec![Element::Block(create_block(vec![Element::String(
+                "50%".to_string()
+            )]))]
+        );
+        assert!(!parser.is_inside_block());
+    }
 }
@@ -1,7 +1,12 @@
 mod block;
+mod conversion;
+#[allow(clippy::module_inception)]
 mod element;
+mod native_macro;
 mod native_operation;
+mod native_type;
 
 pub use self::block::*;
 pub use self::element::*;
 pub use self::native_operation::*;
+pub use self::native_type::*;
@@ -0,0 +1,198 @@
+/// Rustの関数から組み込みの演算を定義する
+///
+/// 引数はスタックの奥から順に型を確認してから取り出し、戻り値はスタックに積む。
+/// 定義した関数は引数と戻り値の型をスタック効果として持つ [`NativeOperation`](crate::NativeOperation) を返す。
+/// 最初の引数を `&mut Stack` にするとブロックの評価などにスタックを使える。
+///
+/// ```
+/// use refactoring_rustack::{native_operation, VirtualMachine};
+///
+/// native_operation! {
+///     /// 斜辺の長さ
+///     fn hypot(a: f64, b: f64) -> f64 {
+///         a.hypot(b)
+///     }
+/// }
+///
+/// let mut virtual_machine = VirtualMachine::new();
+/// virtual_machine.register_operation("hypot", hypot());
+/// ```
+#[macro_export]
+macro_rules! native_operation {
+    {
+        $(#[$meta:meta])*
+        $vis:vis fn $name:ident($stack:ident: &mut Stack $(, $arg:ident: $type:ty)* $(,)?)
+            $(-> $return:ty)? $body:block
+    } => {
+        $crate::native_operation! {
+            @impl [$(#[$meta])*] $vis $name $stack [$($arg: $type),*] [$($return)?] $body
+        }
+    };
+    {
+        $(#[$meta:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $type:ty),* $(,)?) $(-> $return:ty)? $body:block
+    } => {
+        $crate::native_operation! {
+            @impl [$(#[$meta])*] $vis $name _stack [$($arg: $type),*] [$($return)?] $body
+        }
+    };
+    // スタックを使わない関数も未使用のスタックを最初の引数に取る関数として定義する
+    {
+        @impl [$(#[$meta:meta])*] $vis:vis $name:ident $stack:ident
+            [$($arg:ident: $type:ty),*] [$($return:ty)?] $body:block
+    } => {
+        $(#[$meta])*
+        $vis fn $name() -> $crate::NativeOperation {
+            fn operate($stack: &mut $crate::Stack $(, $arg: $type)*) $(-> $return)? $body
+
+            const INPUTS: &[(&str, &str)] =
+                &[$((stringify!($arg), <$type as $crate::NativeType>::NAME)),*];
+            $crate::NativeOperation::new(|stack: &mut $crate::Stack| {
+                // 型が合わない場合にオペランドを失わないように、取り出す前に確認する
+                #[allow(unused_mut, unused_variables)]
+                let mut depth = INPUTS.len();
+                $(
+                    depth -= 1;
+                    <$type as $crate::NativeType>::check(stack.peek(depth)?)?;
+                )*
+                #[allow(unused_mut, unused_variables)]
+                let mut arguments = stack.pop_many(INPUTS.len())?.into_iter();
+                $(let $arg = <$type as $crate::NativeType>::from_element(arguments.next().unwrap())?;)*
+                $crate::NativeReturn::push_to(operate(stack $(, $arg)*), stack)
+            })
+            .with_signature($crate::Signature::new(
+                INPUTS,
+                <$crate::native_operation!(@return $($return)?) as $crate::NativeReturn>::OUTPUTS,
+            ))
+        }
+    };
+    (@return) => { () };
+    (@return $return:ty) => { $return };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::element::tests::create_block;
+    use crate::element::{Block, Element, Symbol};
+    use crate::parser::Parser;
+    use crate::virtual_machine::{RuntimeError, Stack};
+
+    native_operation! {
+        /// 斜辺の長さ
+        fn hypot(a: f64, b: f64) -> f64 {
+            a.hypot(b)
+        }
+    }
+
+    native_operation! {
+        /// 回数を指定してブロックを評価する
+        fn times(stack: &mut Stack, count: i32, body: Block) -> Result<(), RuntimeError> {
+            for _ in 0..count {
+                stack.evaluate_block(&body)?;
+            }
+            Ok(())
+        }
+    }
+
+    native_operation! {
+        /// 割り算の商と余り
+        fn divmod(lhs: i32, rhs: i32) -> Result<(i32, i32), RuntimeError> {
+            if rhs == 0 {
+                return Err(RuntimeError::Custom("division by zero".to_string()));
+            }
+            Ok((lhs / rhs, lhs % rhs))
+        }
+    }
+
+    native_operation! {
+        fn name(symbol: Symbol) -> String {
+            symbol.0
+        }
+    }
+
+    fn evaluate(code: &str) -> (Stack, Result<(), RuntimeError>) {
+        let mut stack = Stack::new();
+        stack.register_operation("hypot", hypot());
+        stack.register_operation("times", times());
+        stack.register_operation("divmod", divmod());
+        stack.register_operation("name", name());
+        let result = Parser::new()
+            .parse(code.to_string())
+            .try_for_each(|element| stack.evaluate(element));
+        (stack, result)
+    }
+
+    #[test]
+    fn test_arguments() {
+        let (stack, result) = evaluate("3 4 hypot 7 2 divmod /x name");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            stack.list(),
+            &vec![
+                Element::Number(5.0),
+                Element::Integer(3),
+                Element::Integer(1),
+                Element::String("x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack_argument() {
+        let (stack, result) = evaluate("0 3 { 2 + } times");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(stack.list(), &vec![Element::Number(6.0)]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(evaluate("3 hypot").1, Err(RuntimeError::StackUnderflow));
+        assert_eq!(
+            evaluate("3 { } hypot").1,
+            Err(RuntimeError::TypeMismatch {
+                expected: "number",
+                found: Element::Block(create_block(vec![])),
+            })
+        );
+        assert_eq!(
+            evaluate("1 0 divmod").1,
+            Err(RuntimeError::Custom("division by zero".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_type_mismatch_keeps_operands() {
+        let (stack, result) = evaluate("1 /x 2 hypot");
+
+        assert_eq!(
+            result,
+            Err(RuntimeError::TypeMismatch {
+                expected: "number",
+                found: Element::Symbol("x".to_string()),
+            })
+        );
+        assert_eq!(
+            stack.list(),
+            &vec![
+                Element::Integer(1),
+                Element::Symbol("x".to_string()),
+                Element::Integer(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_signature() {
+        let signature =
+            |operation: crate::element::NativeOperation| operation.signature().unwrap().to_string();
+
+        assert_eq!(signature(hypot()), "( a:number b:number -- number )");
+        assert_eq!(signature(times()), "( count:integer body:block -- )");
+        assert_eq!(
+            signature(divmod()),
+            "( lhs:integer rhs:integer -- integer integer )"
+        );
+    }
+}
@@ -1,5 +1,96 @@
-use crate::virtual_machine::Stack;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::virtual_machine::{RuntimeError, Stack};
+
+/// 組み込みの演算の関数
+pub type NativeFunction = dyn Fn(&mut Stack) -> Result<(), RuntimeError>;
+
+/// 組み込みの演算のスタック効果 (取り出す引数の名前と型、積む値の型)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Signature {
+    inputs: &'static [(&'static str, &'static str)],
+    outputs: &'static [&'static str],
+}
+
+impl Signature {
+    /// スタック効果を生成する
+    pub const fn new(
+        inputs: &'static [(&'static str, &'static str)],
+        outputs: &'static [&'static str],
+    ) -> Self {
+        Self { inputs, outputs }
+    }
+
+    /// 引数の名前と型をスタックの奥から順に返す
+    pub fn inputs(&self) -> &'static [(&'static str, &'static str)] {
+        self.inputs
+    }
+
+    /// 積む値の型をスタックの奥から順に返す
+    pub fn outputs(&self) -> &'static [&'static str] {
+        self.outputs
+    }
+}
+
+impl fmt::Display for Signature {
+    /// `( lhs:number rhs:number -- number )` の形式で表示する
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(")?;
+        for (name, type_name) in self.inputs {
+            write!(f, " {name}:{type_name}")?;
+        }
+        write!(f, " --")?;
+        for type_name in self.outputs {
+            write!(f, " {type_name}")?;
+        }
+        write!(f, " )")
+    }
+}
 
 /// 組み込みの演算
-#[derive(Debug, PartialEq, Clone)]
-pub struct NativeOperation(pub fn(&mut Stack));
+#[derive(Clone)]
+pub struct NativeOperation {
+    function: Rc<NativeFunction>,
+    signature: Option<Signature>,
+}
+
+impl NativeOperation {
+    /// 関数やクロージャから組み込みの演算を生成する
+    pub fn new(function: impl Fn(&mut Stack) -> Result<(), RuntimeError> + 'static) -> Self {
+        Self {
+            function: Rc::new(function),
+            signature: None,
+        }
+    }
+
+    /// スタック効果を設定する
+    pub fn with_signature(self, signature: Signature) -> Self {
+        Self {
+            signature: Some(signature),
+            ..self
+        }
+    }
+
+    /// スタック効果を返す
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    /// 演算を実行する
+    pub fn call(&self, stack: &mut Stack) -> Result<(), RuntimeError> {
+        (self.function)(stack)
+    }
+}
+
+impl PartialEq for NativeOperation {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(Rc::as_ptr(&self.function), Rc::as_ptr(&other.function))
+    }
+}
+
+impl fmt::Debug for NativeOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NativeOperation")
+    }
+}
@@ -0,0 +1,137 @@
+use crate::virtual_machine::{RuntimeError, Stack};
+
+use super::{Block, Element};
+
+/// 組み込みの演算の引数や戻り値にできる型
+pub trait NativeType: Sized {
+    /// スタック効果に表示する型の名前
+    const NAME: &'static str;
+
+    /// 要素から変換できるかを返す
+    fn accepts(element: &Element) -> bool;
+
+    /// 要素から変換する
+    fn from_element(element: Element) -> Result<Self, RuntimeError>;
+
+    /// 要素に変換する
+    fn into_element(self) -> Element;
+
+    /// 要素を取り出す前に型を確認する (変換できない場合は変換したときと同じエラーを返す)
+    fn check(element: &Element) -> Result<(), RuntimeError> {
+        if Self::accepts(element) {
+            return Ok(());
+        }
+        Self::from_element(element.clone()).map(drop)
+    }
+}
+
+macro_rules! impl_native_type {
+    {$type:ty, $name:literal, $pattern:pat} => {
+        impl NativeType for $type {
+            const NAME: &'static str = $name;
+
+            fn accepts(element: &Element) -> bool {
+                matches!(element, $pattern)
+            }
+
+            fn from_element(element: Element) -> Result<Self, RuntimeError> {
+                element.try_into()
+            }
+
+            fn into_element(self) -> Element {
+                self.into()
+            }
+        }
+    }
+}
+
+impl_native_type!(i32, "integer", Element::Integer(_) | Element::Number(_));
+impl_native_type!(f32, "number", Element::Integer(_) | Element::Number(_));
+impl_native_type!(f64, "number", Element::Integer(_) | Element::Number(_));
+impl_native_type!(bool, "boolean", Element::Integer(_) | Element::Number(_));
+impl_native_type!(String, "string", Element::String(_));
+impl_native_type!(Block, "block", Element::Block(_));
+
+impl NativeType for Element {
+    const NAME: &'static str = "any";
+
+    fn accepts(_: &Element) -> bool {
+        true
+    }
+
+    fn from_element(element: Element) -> Result<Self, RuntimeError> {
+        Ok(element)
+    }
+
+    fn into_element(self) -> Element {
+        self
+    }
+}
+
+/// シンボルの名前
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol(pub String);
+
+impl NativeType for Symbol {
+    const NAME: &'static str = "symbol";
+
+    fn accepts(element: &Element) -> bool {
+        matches!(element, Element::Symbol(_))
+    }
+
+    fn from_element(element: Element) -> Result<Self, RuntimeError> {
+        match element {
+            Element::Symbol(name) => Ok(Symbol(name)),
+            found => Err(RuntimeError::TypeMismatch {
+                expected: Self::NAME,
+                found,
+            }),
+        }
+    }
+
+    fn into_element(self) -> Element {
+        Element::Symbol(self.0)
+    }
+}
+
+/// 組み込みの演算の戻り値にできる型
+pub trait NativeReturn {
+    /// スタックに積む値の型の名前
+    const OUTPUTS: &'static [&'static str];
+
+    /// 戻り値をスタックに積む
+    fn push_to(self, stack: &mut Stack) -> Result<(), RuntimeError>;
+}
+
+impl<T: NativeType> NativeReturn for T {
+    const OUTPUTS: &'static [&'static str] = &[T::NAME];
+
+    fn push_to(self, stack: &mut Stack) -> Result<(), RuntimeError> {
+        stack.push(self.into_element())
+    }
+}
+
+impl NativeReturn for () {
+    const OUTPUTS: &'static [&'static str] = &[];
+
+    fn push_to(self, _: &mut Stack) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+}
+
+impl<A: NativeType, B: NativeType> NativeReturn for (A, B) {
+    const OUTPUTS: &'static [&'static str] = &[A::NAME, B::NAME];
+
+    fn push_to(self, stack: &mut Stack) -> Result<(), RuntimeError> {
+        stack.push(self.0.into_element())?;
+        stack.push(self.1.into_element())
+    }
+}
+
+impl<T: NativeReturn> NativeReturn for Result<T, RuntimeError> {
+    const OUTPUTS: &'static [&'static str] = T::OUTPUTS;
+
+    fn push_to(self, stack: &mut Stack) -> Result<(), RuntimeError> {
+        self?.push_to(stack)
+    }
+}
@@ -1,48 +1,217 @@
-use std::io::BufRead;
-use std::io::BufReader;
-
-use parser::Parser;
-use virtual_machine::VirtualMachine;
-
-mod element;
-mod parser;
-mod virtual_machine;
-
-fn main() {
-    if let Some(file) = std::env::args()
-        .nth(1)
-        .and_then(|f| std::fs::File::open(f).ok())
-    {
-        parse_batch(BufReader::new(file));
-    } else {
-        parse_interactive();
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use cli::{
+    check_source, disassemble, format_source, run_repl, Command, DebugConsole, Options, STDIN,
+    USAGE,
+};
+use refactoring_rustack::{Debugger, Profiler, RuntimeError, Tracer, VirtualMachine};
+
+mod cli;
+
+/// コマンドライン引数の誤りの終了コード
+const USAGE_ERROR: u8 = 2;
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1), std::io::stdin().is_terminal()) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {message}\n\n{USAGE}");
+            return ExitCode::from(USAGE_ERROR);
+        }
+    };
+
+    match &options.command {
+        Command::Help => {
+            println!("{USAGE}");
+            ExitCode::SUCCESS
+        }
+        Command::Check(files) => check_files(files),
+        Command::Fmt { files, check } => format_files(files, *check),
+        Command::Disasm(files) => disassemble_files(files),
+        Command::Run(_) | Command::Eval(_) | Command::Repl => match run(options) {
+            Ok(()) => ExitCode::SUCCESS,
+            // `exit` は0から255の終了コードだけを受け付ける
+            Err(RuntimeError::Exit(status)) => {
+                u8::try_from(status).map_or(ExitCode::FAILURE, ExitCode::from)
+            }
+            Err(error) => {
+                eprintln!("error: {error}");
+                ExitCode::FAILURE
+            }
+        },
     }
 }
 
-/// 一括でパースして処理する
-fn parse_batch(source: impl BufRead) {
-    let mut virtual_machine = VirtualMachine::new();
-    let mut parser = Parser::new();
+/// 仮想マシンを生成してプログラムを実行する
+fn run(options: Options) -> Result<(), RuntimeError> {
+    let mut virtual_machine = if options.bare {
+        VirtualMachine::bare()
+    } else {
+        VirtualMachine::new()
+    };
+    virtual_machine.set_search_path(options.search_path);
+    virtual_machine.set_args(options.args);
+    if let Some(format) = options.trace {
+        let tracer = Tracer::new(std::io::stderr(), format);
+        virtual_machine.set_tracer(Some(Box::new(tracer)));
+    }
+    if options.profile || options.folded.is_some() {
+        virtual_machine.set_profiler(Some(Profiler::new()));
+    }
 
-    for line in source.lines().flatten() {
-        for element in parser.parse(line) {
-            virtual_machine.evaluate(element);
+    let result = match options.command {
+        Command::Run(files) => {
+            // 途中まで実行してから失敗しないように、先にすべてのファイルがあるか確認する
+            if let Some(missing) = files
+                .iter()
+                .find(|path| *path != STDIN && !Path::new(path).is_file())
+            {
+                return Err(RuntimeError::Io(format!("{missing}: no such file")));
+            }
+            files.iter().try_for_each(|path| {
+                if path == STDIN {
+                    run_stdin(&mut virtual_machine, options.debug)
+                } else if options.debug {
+                    run_debug(path, &mut virtual_machine)
+                } else {
+                    virtual_machine.run_file(path)
+                }
+            })
         }
+        Command::Eval(code) => virtual_machine.eval_str(&code),
+        _ => return run_repl(virtual_machine),
+    };
+
+    if let Some(profiler) = virtual_machine.profiler() {
+        print_profile(profiler, options.folded.as_deref());
     }
+    result
+}
 
-    virtual_machine.print_outputs();
+/// ファイルを読み込む (読み込めない場合はエラーを表示する)
+fn read_source(path: &str) -> Option<String> {
+    match std::fs::read_to_string(path) {
+        Ok(source) => Some(source),
+        Err(error) => {
+            eprintln!("error: {path}: {error}");
+            None
+        }
+    }
 }
 
-/// 標準入力をインタラクティブにパースして処理する
-fn parse_interactive() {
-    let mut virtual_machine = VirtualMachine::new();
-    let mut parser = Parser::new();
+/// ファイルの構文を確認し、誤りを表示する
+fn check_files(files: &[String]) -> ExitCode {
+    let mut ok = true;
+    for path in files {
+        let Some(source) = read_source(path) else {
+            ok = false;
+            continue;
+        };
+        for problem in check_source(&source) {
+            eprintln!("{path}:{problem}");
+            ok = false;
+        }
+    }
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
 
-    for line in std::io::stdin().lines().flatten() {
-        for element in parser.parse(line) {
-            virtual_machine.evaluate(element);
+/// ファイルを整形して書き換える (`check` の場合は整形されていないファイルを表示する)
+fn format_files(files: &[String], check: bool) -> ExitCode {
+    let mut ok = true;
+    for path in files {
+        let Some(source) = read_source(path) else {
+            ok = false;
+            continue;
+        };
+        let formatted = format_source(&source);
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{path}: not formatted");
+            ok = false;
+        } else if let Err(error) = std::fs::write(path, formatted) {
+            eprintln!("error: {path}: {error}");
+            ok = false;
         }
+    }
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// ファイルをパースした要素を表示する
+fn disassemble_files(files: &[String]) -> ExitCode {
+    let mut ok = true;
+    for path in files {
+        let Some(source) = read_source(path) else {
+            ok = false;
+            continue;
+        };
+        if files.len() > 1 {
+            println!("{path}:");
+        }
+        if let Err(error) = disassemble(&source, &mut std::io::stdout()) {
+            eprintln!("error: {error}");
+            ok = false;
+        }
+    }
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// 標準入力から読み込んだプログラムを実行する
+fn run_stdin(virtual_machine: &mut VirtualMachine, debug: bool) -> Result<(), RuntimeError> {
+    if debug {
+        // デバッガのコマンドも標準入力から読み込むため併用できない
+        return Err(RuntimeError::Io(
+            "cannot debug a program read from stdin".to_string(),
+        ));
+    }
+    // プログラムの `readline` などが標準入力を読めるように、先にすべて読み込む
+    let source = std::io::read_to_string(std::io::stdin())
+        .map_err(|error| RuntimeError::Io(format!("{STDIN}: {error}")))?;
+    virtual_machine.eval_str(&source)
+}
+
+/// 標準入力から操作するデバッガを付けてファイルを処理する
+fn run_debug(path: &str, virtual_machine: &mut VirtualMachine) -> Result<(), RuntimeError> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|error| RuntimeError::Io(format!("{path}: {error}")))?;
+    let lines: Vec<String> = source.lines().map(str::to_string).collect();
+    let console = DebugConsole::new(
+        Some(PathBuf::from(path)),
+        lines,
+        std::io::stdin().lock(),
+        std::io::stdout(),
+    );
+    virtual_machine.set_debugger(Some(Debugger::new(console)));
 
-        println!("stack: {:?}", virtual_machine.stack().list());
+    virtual_machine.run_file(path)
+}
+
+/// プロファイルの結果を標準エラー出力に表示し、指定されていれば folded stack 形式でファイルに書き出す
+fn print_profile(profiler: &Profiler, folded: Option<&str>) {
+    if let Err(error) = profiler.write_report(&mut std::io::stderr()) {
+        eprintln!("error: failed to write profile: {error}");
+    }
+
+    if let Some(path) = folded {
+        let result =
+            std::fs::File::create(path).and_then(|mut file| profiler.write_folded(&mut file));
+        if let Err(error) = result {
+            eprintln!("error: failed to write {path}: {error}");
+        }
     }
 }
@@ -6,35 +6,102 @@ use crate::element::{Block, Element};
 #[derive(Debug)]
 pub struct Parser {
     blocks: Rc<RefCell<Vec<Block>>>,
+    line: usize,
 }
 
 impl Parser {
     pub fn new() -> Self {
         Self {
             blocks: Rc::new(RefCell::new(vec![])),
+            line: 0,
         }
     }
 
     pub fn parse(&mut self, line: String) -> ParserIterator {
-        let words: Vec<String> = line.split(" ").map(str::to_string).collect();
+        let words = split_words(&line);
+        self.line += 1;
 
         ParserIterator {
             iter: words.into_iter(),
             blocks: self.blocks.clone(),
+            line: self.line,
         }
     }
+
+    /// 閉じていないブロックがあるか
+    pub fn is_inside_block(&self) -> bool {
+        !self.blocks.borrow().is_empty()
+    }
+
+    /// 閉じていない最も外側のブロックが始まる行番号を返す
+    pub fn open_block_line(&self) -> Option<usize> {
+        self.blocks.borrow().first().map(Block::line)
+    }
+
+    /// 最後にパースした行の行番号を返す
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+/// 行を空白で語に分割する (文字列リテラルは空白を含めて1つの語にし、コメントは取り除く)
+pub fn split_words(line: &str) -> Vec<String> {
+    split_line(line).0
+}
+
+/// 行を語と `%` から行末までのコメントに分割する
+///
+/// 文字列リテラルは空白を含めて1つの語にし、文字列リテラルの中の `%` はコメントにしない。
+pub fn split_line(line: &str) -> (Vec<String>, Option<&str>) {
+    let mut words = vec![];
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(index, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '%' {
+            return (words, Some(line[index..].trim_end()));
+        }
+
+        let mut word = String::new();
+        if c == '"' {
+            word.push(chars.next().unwrap().1);
+            while let Some((_, c)) = chars.next() {
+                word.push(c);
+                match c {
+                    '\\' => word.extend(chars.next().map(|(_, c)| c)),
+                    '"' => break,
+                    _ => {}
+                }
+            }
+        } else {
+            while let Some((_, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+    (words, None)
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Debug)]
 pub struct ParserIterator {
     iter: IntoIter<String>,
     blocks: Rc<RefCell<Vec<Block>>>,
+    line: usize,
 }
 
 impl Iterator for ParserIterator {
     type Item = Element;
 
     fn next(&mut self) -> Option<Self::Item> {
-        Element::parse(&mut self.iter, &mut self.blocks)
+        Element::parse(&mut self.iter, &mut self.blocks, self.line)
     }
 }
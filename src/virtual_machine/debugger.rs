@@ -0,0 +1,446 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::element::Element;
+
+use super::error::RuntimeError;
+use super::stack::Stack;
+
+/// ブレークポイント
+#[derive(Debug, Default, Clone)]
+pub struct Breakpoints {
+    words: HashSet<String>,
+    lines: HashSet<(Option<PathBuf>, usize)>,
+}
+
+impl Breakpoints {
+    /// 語の呼び出しにブレークポイントを設定する
+    pub fn add_word(&mut self, name: &str) {
+        self.words.insert(name.to_string());
+    }
+
+    /// 語の呼び出しのブレークポイントを削除する
+    pub fn remove_word(&mut self, name: &str) -> bool {
+        self.words.remove(name)
+    }
+
+    /// ファイルの行にブレークポイントを設定する (`None` はファイルから読み込んでいないソースコード)
+    pub fn add_line(&mut self, file: Option<&Path>, line: usize) {
+        self.lines.insert((file.map(Path::to_path_buf), line));
+    }
+
+    /// ファイルの行のブレークポイントを削除する
+    pub fn remove_line(&mut self, file: Option<&Path>, line: usize) -> bool {
+        self.lines.remove(&(file.map(Path::to_path_buf), line))
+    }
+
+    /// ブレークポイントを設定した語を返す
+    pub fn words(&self) -> impl Iterator<Item = &String> {
+        self.words.iter()
+    }
+
+    /// ブレークポイントを設定したファイルと行を返す
+    pub fn lines(&self) -> impl Iterator<Item = (Option<&Path>, usize)> {
+        self.lines
+            .iter()
+            .map(|(file, line)| (file.as_deref(), *line))
+    }
+
+    /// すべてのブレークポイントを削除する
+    pub fn clear(&mut self) {
+        self.words.clear();
+        self.lines.clear();
+    }
+
+    /// 語の呼び出しに一致するか
+    fn matches_word(&self, element: &Element) -> bool {
+        matches!(element, Element::Operation(name) if self.words.contains(name))
+    }
+
+    /// ファイルの行に一致するか
+    fn matches_line(&self, file: Option<&Path>, line: usize) -> bool {
+        line != 0
+            && self
+                .lines
+                .iter()
+                .any(|(target, target_line)| *target_line == line && target.as_deref() == file)
+    }
+}
+
+/// 一時停止した理由
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PauseReason {
+    /// ステップ実行
+    Step,
+    /// ブレークポイント
+    Breakpoint,
+}
+
+/// 一時停止後の実行方法
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DebugCommand {
+    /// 次の要素で停止する
+    Step,
+    /// 語の呼び出しを飛ばして次の要素で停止する
+    Next,
+    /// 現在の語から戻るまで実行する
+    Finish,
+    /// 次のブレークポイントまで実行する
+    Continue,
+    /// 実行を中断する
+    Abort,
+}
+
+/// 一時停止中の仮想マシンの状態
+#[derive(Debug)]
+pub struct DebugState<'a> {
+    stack: &'a Stack,
+    element: &'a Element,
+    reason: PauseReason,
+}
+
+impl DebugState<'_> {
+    /// オペランドスタックを返す
+    pub fn operand_stack(&self) -> &[Element] {
+        self.stack.list()
+    }
+
+    /// 辞書スタックを返す
+    pub fn dictionaries(&self) -> &[HashMap<String, Element>] {
+        self.stack.dictionaries()
+    }
+
+    /// 呼び出し中の語の名前を呼び出し順に返す
+    pub fn call_stack(&self) -> &[String] {
+        self.stack.call_stack()
+    }
+
+    /// 次に評価する要素を返す
+    pub fn next_element(&self) -> &Element {
+        self.element
+    }
+
+    /// 次に評価する要素の行番号を返す (不明な場合は0)
+    pub fn line(&self) -> usize {
+        self.stack.line()
+    }
+
+    /// 次に評価する要素を読み込んだファイルを返す (ファイルから読み込んでいない場合は `None`)
+    pub fn file(&self) -> Option<&Path> {
+        self.stack.current_file()
+    }
+
+    /// 一時停止した理由を返す
+    pub fn reason(&self) -> PauseReason {
+        self.reason
+    }
+}
+
+/// 一時停止したときに次の実行方法を決める
+pub trait DebugHandler {
+    fn pause(&mut self, state: &DebugState, breakpoints: &mut Breakpoints) -> DebugCommand;
+}
+
+impl<F> DebugHandler for F
+where
+    F: FnMut(&DebugState, &mut Breakpoints) -> DebugCommand,
+{
+    fn pause(&mut self, state: &DebugState, breakpoints: &mut Breakpoints) -> DebugCommand {
+        self(state, breakpoints)
+    }
+}
+
+/// 実行モード
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    Step,
+    Next { depth: usize },
+    Finish { depth: usize },
+    Continue,
+}
+
+/// 要素を1つずつ評価しながら一時停止させるデバッガ
+pub struct Debugger {
+    handler: Box<dyn DebugHandler>,
+    breakpoints: Breakpoints,
+    mode: Mode,
+    last_file: Option<PathBuf>,
+    last_line: usize,
+}
+
+impl Debugger {
+    /// 最初の要素で一時停止するデバッガを生成する
+    pub fn new(handler: impl DebugHandler + 'static) -> Self {
+        Self {
+            handler: Box::new(handler),
+            breakpoints: Breakpoints::default(),
+            mode: Mode::Step,
+            last_file: None,
+            last_line: 0,
+        }
+    }
+
+    /// ブレークポイントに達するまで一時停止しないデバッガを生成する
+    pub fn with_breakpoints(
+        handler: impl DebugHandler + 'static,
+        breakpoints: Breakpoints,
+    ) -> Self {
+        Self {
+            breakpoints,
+            mode: Mode::Continue,
+            ..Self::new(handler)
+        }
+    }
+
+    /// ブレークポイントを返す
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    /// ブレークポイントを変更する
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// 要素を評価する前に一時停止するかを判定し、必要ならハンドラを呼ぶ
+    pub(crate) fn before_evaluate(
+        &mut self,
+        stack: &Stack,
+        element: &Element,
+    ) -> Result<(), RuntimeError> {
+        let depth = stack.call_stack().len();
+        let file = stack.current_file();
+        let line = stack.line();
+        let line_changed = line != self.last_line || file != self.last_file.as_deref();
+        if file != self.last_file.as_deref() {
+            self.last_file = file.map(Path::to_path_buf);
+        }
+        self.last_line = line;
+
+        let reason = if self.breakpoints.matches_word(element)
+            || (line_changed && self.breakpoints.matches_line(file, line))
+        {
+            PauseReason::Breakpoint
+        } else {
+            match self.mode {
+                Mode::Step => PauseReason::Step,
+                Mode::Next { depth: target } if depth <= target => PauseReason::Step,
+                Mode::Finish { depth: target } if depth < target => PauseReason::Step,
+                _ => return Ok(()),
+            }
+        };
+
+        let state = DebugState {
+            stack,
+            element,
+            reason,
+        };
+        self.mode = match self.handler.pause(&state, &mut self.breakpoints) {
+            DebugCommand::Step => Mode::Step,
+            DebugCommand::Next => Mode::Next { depth },
+            DebugCommand::Finish => Mode::Finish { depth },
+            DebugCommand::Continue => Mode::Continue,
+            DebugCommand::Abort => return Err(RuntimeError::Interrupted),
+        };
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Debugger")
+            .field("breakpoints", &self.breakpoints)
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{Breakpoints, DebugCommand, DebugState, Debugger, PauseReason};
+    use crate::element::Element;
+    use crate::virtual_machine::{RuntimeError, VirtualMachine};
+
+    /// 一時停止した状態
+    type Pause = (Element, Vec<String>, usize, PauseReason);
+
+    fn run(
+        lines: &str,
+        breakpoints: Option<Breakpoints>,
+        mut commands: Vec<DebugCommand>,
+    ) -> (Vec<Pause>, Result<(), RuntimeError>) {
+        let pauses = Rc::new(RefCell::new(vec![]));
+        let recorder = pauses.clone();
+        commands.reverse();
+        let handler = move |state: &DebugState, _: &mut Breakpoints| {
+            recorder.borrow_mut().push((
+                state.next_element().clone(),
+                state.call_stack().to_vec(),
+                state.line(),
+                state.reason(),
+            ));
+            commands.pop().unwrap_or(DebugCommand::Continue)
+        };
+        let debugger = match breakpoints {
+            Some(breakpoints) => Debugger::with_breakpoints(handler, breakpoints),
+            None => Debugger::new(handler),
+        };
+
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_debugger(Some(debugger));
+        let result = virtual_machine.eval_str(lines);
+
+        let pauses = pauses.borrow().clone();
+        (pauses, result)
+    }
+
+    #[test]
+    fn test_step() {
+        let (pauses, result) = run("1 2 +", None, vec![DebugCommand::Step; 3]);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            elements(pauses),
+            vec![
+                Element::Integer(1),
+                Element::Integer(2),
+                Element::Operation("+".to_string())
+            ]
+        );
+    }
+
+    fn elements(pauses: Vec<Pause>) -> Vec<Element> {
+        pauses.into_iter().map(|(element, ..)| element).collect()
+    }
+
+    #[test]
+    fn test_next() {
+        let lines = "/double { 2 * } def\n10 double\npop";
+        let (pauses, _) = run(lines, None, vec![DebugCommand::Next; 5]);
+
+        assert_eq!(
+            elements(pauses)[3..],
+            vec![
+                Element::Integer(10),
+                Element::Operation("double".to_string()),
+                Element::Operation("pop".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_finish() {
+        let lines = "/double { 2 * } def\n10 double\npop";
+        let mut commands = vec![DebugCommand::Next; 4];
+        commands.extend([DebugCommand::Step, DebugCommand::Step, DebugCommand::Finish]);
+        let (pauses, _) = run(lines, None, commands);
+
+        assert_eq!(
+            elements(pauses)[3..],
+            vec![
+                Element::Integer(10),
+                Element::Operation("double".to_string()),
+                Element::Integer(2),
+                Element::Operation("*".to_string()),
+                Element::Operation("pop".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_define_pauses_once() {
+        let (pauses, result) = run("/x { 1 } def", None, vec![DebugCommand::Step; 4]);
+
+        let elements = elements(pauses);
+        assert_eq!(result, Ok(()));
+        assert_eq!(elements.len(), 3);
+        assert_eq!(elements[0], Element::Symbol("x".to_string()));
+        assert!(matches!(elements[1], Element::Block(_)));
+        assert_eq!(elements[2], Element::Operation("def".to_string()));
+    }
+
+    #[test]
+    fn test_word_breakpoint() {
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.add_word("*");
+        let lines = "/double { 2 * } def\n/quad { double double } def\n1 quad";
+        let (pauses, _) = run(lines, Some(breakpoints), vec![]);
+
+        assert_eq!(
+            pauses,
+            vec![
+                (
+                    Element::Operation("*".to_string()),
+                    vec!["quad".to_string(), "double".to_string()],
+                    1,
+                    PauseReason::Breakpoint
+                ),
+                (
+                    Element::Operation("*".to_string()),
+                    vec!["quad".to_string(), "double".to_string()],
+                    1,
+                    PauseReason::Breakpoint
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_breakpoint() {
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.add_line(None, 3);
+        let lines = "/f {\n  1\n  2 +\n} def\n0 f f";
+        let (pauses, _) = run(lines, Some(breakpoints), vec![]);
+
+        assert_eq!(
+            pauses
+                .into_iter()
+                .map(|(element, call_stack, line, _)| (element, call_stack, line))
+                .collect::<Vec<_>>(),
+            vec![
+                (Element::Integer(2), vec!["f".to_string()], 3),
+                (Element::Integer(2), vec!["f".to_string()], 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_breakpoint_in_file() {
+        let directory =
+            std::env::temp_dir().join(format!("rustack-debugger-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let main = directory.join("main.txt");
+        std::fs::write(&main, "\"lib.txt\" import\n1\n2").unwrap();
+        std::fs::write(directory.join("lib.txt"), "10\n20\n30").unwrap();
+
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.add_line(Some(&main), 3);
+        let pauses = Rc::new(RefCell::new(vec![]));
+        let recorder = pauses.clone();
+        let handler = move |state: &DebugState, _: &mut Breakpoints| {
+            let file = state.file().map(|file| file.to_path_buf());
+            recorder
+                .borrow_mut()
+                .push((state.next_element().clone(), file, state.line()));
+            DebugCommand::Continue
+        };
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_debugger(Some(Debugger::with_breakpoints(handler, breakpoints)));
+        let result = virtual_machine.run_file(&main);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(*pauses.borrow(), vec![(Element::Integer(2), Some(main), 3)]);
+    }
+
+    #[test]
+    fn test_abort() {
+        let (_, result) = run("{ 1 } { } while", None, vec![DebugCommand::Abort]);
+
+        assert_eq!(result, Err(RuntimeError::Interrupted));
+    }
+}
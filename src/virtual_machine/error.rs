@@ -0,0 +1,123 @@
+use std::fmt;
+
+use crate::element::Element;
+
+/// 実行時エラー
+#[derive(Debug, PartialEq, Clone)]
+pub enum RuntimeError {
+    /// 命令数の上限を超えた
+    InstructionLimitExceeded { limit: u64 },
+    /// オペランドスタックの深さの上限を超えた
+    StackDepthExceeded { limit: usize },
+    /// 辞書スタックの深さの上限を超えた
+    DictionaryDepthExceeded { limit: usize },
+    /// 要素の総数の上限を超えた
+    ElementLimitExceeded { limit: usize },
+    /// 要素の総バイト数の上限を超えた
+    MemoryLimitExceeded { limit: usize },
+    /// 出力の数の上限を超えた
+    OutputLimitExceeded { limit: usize },
+    /// 実行が中断された
+    Interrupted,
+    /// `exit` でプログラムが終了した
+    Exit(i32),
+    /// 語が定義されていない
+    UndefinedWord(String),
+    /// 名前空間の外から公開されていない語を呼び出した
+    NotExported(String),
+    /// スタックの要素が足りない
+    StackUnderflow,
+    /// ソースの終わりまでにブロックが閉じていない
+    UnclosedBlock { line: usize },
+    /// 位置や個数が範囲外
+    OutOfRange { name: &'static str, value: i32 },
+    /// 要素の型が期待した型と異なる
+    TypeMismatch {
+        expected: &'static str,
+        found: Element,
+    },
+    /// ソースの読み込みに失敗した
+    Io(String),
+    /// 読み込むファイルが見つからない
+    ModuleNotFound(String),
+    /// ファイルの読み込みが循環している
+    ImportCycle(Vec<String>),
+    /// 読み込んだファイルでエラーが発生した
+    Import {
+        /// 読み込みを行った位置 (`ファイル:行`)
+        site: String,
+        path: String,
+        reason: Box<RuntimeError>,
+    },
+    /// 組み込みの演算で発生したエラー
+    Custom(String),
+}
+
+impl RuntimeError {
+    /// 実行全体を止めるエラー (終了、中断、資源の上限) か
+    ///
+    /// これらは読み込んだファイルの中で発生しても [`RuntimeError::Import`] で包まずにそのまま返す。
+    pub fn stops_execution(&self) -> bool {
+        matches!(
+            self,
+            RuntimeError::Exit(_)
+                | RuntimeError::Interrupted
+                | RuntimeError::InstructionLimitExceeded { .. }
+                | RuntimeError::StackDepthExceeded { .. }
+                | RuntimeError::DictionaryDepthExceeded { .. }
+                | RuntimeError::ElementLimitExceeded { .. }
+                | RuntimeError::MemoryLimitExceeded { .. }
+                | RuntimeError::OutputLimitExceeded { .. }
+        )
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::InstructionLimitExceeded { limit } => {
+                write!(f, "instruction limit exceeded: {limit} steps")
+            }
+            RuntimeError::StackDepthExceeded { limit } => {
+                write!(f, "operand stack depth exceeded: {limit} elements")
+            }
+            RuntimeError::DictionaryDepthExceeded { limit } => {
+                write!(f, "dictionary stack depth exceeded: {limit} dictionaries")
+            }
+            RuntimeError::ElementLimitExceeded { limit } => {
+                write!(f, "live element limit exceeded: {limit} elements")
+            }
+            RuntimeError::MemoryLimitExceeded { limit } => {
+                write!(f, "memory limit exceeded: {limit} bytes")
+            }
+            RuntimeError::OutputLimitExceeded { limit } => {
+                write!(f, "output limit exceeded: {limit} outputs")
+            }
+            RuntimeError::Interrupted => write!(f, "interrupted"),
+            RuntimeError::Exit(status) => write!(f, "exit with status {status}"),
+            RuntimeError::UndefinedWord(name) => write!(f, "{name:?} is undefined"),
+            RuntimeError::NotExported(name) => write!(f, "{name:?} is not exported"),
+            RuntimeError::StackUnderflow => write!(f, "stack underflow"),
+            RuntimeError::UnclosedBlock { line } => {
+                write!(f, "unclosed block starting at line {line}")
+            }
+            RuntimeError::OutOfRange { name, value } => write!(f, "{name} {value} is out of range"),
+            RuntimeError::TypeMismatch { expected, found } => {
+                write!(
+                    f,
+                    "expected {expected}, found {} {found}",
+                    found.type_name()
+                )
+            }
+            RuntimeError::Io(message) => write!(f, "{message}"),
+            RuntimeError::ModuleNotFound(path) => write!(f, "module {path:?} not found"),
+            RuntimeError::ImportCycle(chain) => write!(f, "import cycle: {}", chain.join(" -> ")),
+            RuntimeError::Import { site, path, reason } => {
+                write!(f, "{site}: cannot import {path:?}: {reason}")
+            }
+            RuntimeError::Custom(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
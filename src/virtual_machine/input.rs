@@ -0,0 +1,138 @@
+use std::fmt;
+use std::io::{BufRead, Cursor, Read};
+
+/// `readline` などで読み込む入力元
+pub trait InputSource {
+    /// 1行読み込む (末尾の改行は含まず、入力の終わりでは `None` を返す)
+    fn read_line(&mut self) -> std::io::Result<Option<String>>;
+
+    /// 残りの入力をすべて読み込む
+    fn read_all(&mut self) -> std::io::Result<String>;
+}
+
+impl fmt::Debug for dyn InputSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("InputSource")
+    }
+}
+
+/// 改行を取り除いた行を返す (入力の終わりでは `None` を返す)
+fn trim_line(mut line: String, read: usize) -> Option<String> {
+    if read == 0 {
+        return None;
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Some(line)
+}
+
+/// 標準入力から読み込む (読み込むたびに標準入力をロックする)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdinSource;
+
+impl InputSource for StdinSource {
+    fn read_line(&mut self) -> std::io::Result<Option<String>> {
+        let mut line = String::new();
+        let read = std::io::stdin().read_line(&mut line)?;
+        Ok(trim_line(line, read))
+    }
+
+    fn read_all(&mut self) -> std::io::Result<String> {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        Ok(text)
+    }
+}
+
+/// [`BufRead`] を実装した入力元から読み込む
+#[derive(Debug)]
+pub struct ReaderSource<R>(pub R);
+
+impl ReaderSource<Cursor<String>> {
+    /// 文字列から読み込む入力元を生成する
+    pub fn from_string(text: impl Into<String>) -> Self {
+        Self(Cursor::new(text.into()))
+    }
+}
+
+impl<R: BufRead> InputSource for ReaderSource<R> {
+    fn read_line(&mut self) -> std::io::Result<Option<String>> {
+        let mut line = String::new();
+        let read = self.0.read_line(&mut line)?;
+        Ok(trim_line(line, read))
+    }
+
+    fn read_all(&mut self) -> std::io::Result<String> {
+        let mut text = String::new();
+        self.0.read_to_string(&mut text)?;
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReaderSource;
+    use crate::element::Element;
+    use crate::virtual_machine::{OutputBuffer, RuntimeError, VirtualMachine};
+
+    fn run(code: &str, input: &str) -> (String, Result<(), RuntimeError>, Vec<Element>) {
+        let output = OutputBuffer::new();
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_output(Box::new(output.clone()));
+        virtual_machine.set_input(Box::new(ReaderSource::from_string(input)));
+        let result = virtual_machine.eval_str(code);
+        (
+            output.text(),
+            result,
+            virtual_machine.stack().list().clone(),
+        )
+    }
+
+    #[test]
+    fn test_readline() {
+        let (text, result, stack) = run(
+            r#"{ readline } { "> {}\n" printf } while"#,
+            "first\r\nsecond\n\nlast",
+        );
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(text, "> first\n> second\n> \n> last\n");
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn test_readnumber() {
+        let (text, result, _) = run("0 { readnumber } { + } while =", "1\n 2.5 \n3\n");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(text, "6.5\n");
+
+        let (_, result, _) = run("readnumber", "abc\n");
+        assert_eq!(
+            result,
+            Err(RuntimeError::TypeMismatch {
+                expected: "number",
+                found: Element::String("abc".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn test_readall() {
+        let (_, result, stack) = run("readline pop readall readall", "head\nrest\nof input\n");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            stack,
+            vec![
+                Element::String("head".to_string()),
+                Element::String("rest\nof input\n".to_string()),
+                Element::String("".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// 実行中の仮想マシンを別のスレッドから中断するためのハンドル
+#[derive(Debug, Default, Clone)]
+pub struct InterruptHandle {
+    requested: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    /// ハンドルを生成する
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 中断を要求する
+    pub fn interrupt(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// 中断が要求されているかを返す
+    pub fn is_interrupted(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// 中断の要求を取り消す
+    pub fn clear(&self) {
+        self.requested.store(false, Ordering::SeqCst);
+    }
+
+    /// 中断の要求を取り出して取り消す
+    pub(crate) fn take(&self) -> bool {
+        self.requested.swap(false, Ordering::SeqCst)
+    }
+}
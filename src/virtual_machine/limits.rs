@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::ops::{AddAssign, SubAssign};
+
+use crate::element::Element;
+
+/// 実行時に使用できる資源の上限 (`None` の場合は無制限)
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Limits {
+    /// 評価できる要素の数
+    pub instructions: Option<u64>,
+    /// オペランドスタックの深さ
+    pub stack_depth: Option<usize>,
+    /// 辞書スタックの深さ
+    pub dictionary_depth: Option<usize>,
+    /// スタックと辞書に存在する要素の総数
+    pub elements: Option<usize>,
+    /// スタックと辞書に存在する要素の総バイト数
+    pub bytes: Option<usize>,
+    /// 出力の数
+    pub outputs: Option<usize>,
+}
+
+impl Limits {
+    /// 上限を設定しない
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+}
+
+/// スタックと辞書に存在する要素の総数と総バイト数
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub(crate) struct Usage {
+    pub(crate) elements: usize,
+    pub(crate) bytes: usize,
+}
+
+impl Usage {
+    /// スタックに積んだ要素の使用量
+    pub(crate) fn of_element(element: &Element) -> Self {
+        Self {
+            elements: element.count(),
+            bytes: element.size(),
+        }
+    }
+
+    /// 辞書の項目の使用量
+    pub(crate) fn of_entry(name: &str, element: &Element) -> Self {
+        Self {
+            elements: element.count(),
+            bytes: name.len() + element.size(),
+        }
+    }
+
+    /// 辞書のすべての項目の使用量
+    pub(crate) fn of_dictionary(dictionary: &HashMap<String, Element>) -> Self {
+        let mut usage = Self::default();
+        for (name, element) in dictionary {
+            usage += Self::of_entry(name, element);
+        }
+        usage
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.elements += other.elements;
+        self.bytes += other.bytes;
+    }
+}
+
+impl SubAssign for Usage {
+    fn sub_assign(&mut self, other: Self) {
+        self.elements -= other.elements;
+        self.bytes -= other.bytes;
+    }
+}
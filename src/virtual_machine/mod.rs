@@ -1,6 +1,24 @@
+mod debugger;
+mod error;
+mod input;
+mod interrupt;
+mod limits;
+mod module;
+mod output;
+mod profiler;
 mod stack;
 mod stack_helper;
+mod tracer;
+#[allow(clippy::module_inception)]
 mod virtual_machine;
 
+pub use self::debugger::*;
+pub use self::error::*;
+pub use self::input::*;
+pub use self::interrupt::*;
+pub use self::limits::*;
+pub use self::output::*;
+pub use self::profiler::*;
 pub use self::stack::*;
+pub use self::tracer::*;
 pub use self::virtual_machine::*;
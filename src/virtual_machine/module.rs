@@ -0,0 +1,352 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::element::Element;
+
+use super::error::RuntimeError;
+
+/// `module` ... `end` で定義した名前空間
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Namespace {
+    pub(crate) words: HashMap<String, Element>,
+    pub(crate) exports: HashSet<String>,
+}
+
+/// 定義中の名前空間
+#[derive(Debug, Clone)]
+pub(crate) struct OpenNamespace {
+    pub(crate) name: String,
+    /// 名前空間の辞書を積んだ後の辞書スタックの深さ
+    pub(crate) depth: usize,
+    pub(crate) exports: HashSet<String>,
+}
+
+/// 読み込み中のファイル
+#[derive(Debug)]
+struct SourceFile {
+    /// 表示用のパス
+    path: PathBuf,
+    /// 同じファイルか判定するための正規化したパス
+    canonical: PathBuf,
+}
+
+/// ファイルの読み込み状態と検索パス
+#[derive(Debug, Default)]
+pub(crate) struct Modules {
+    search_path: Vec<PathBuf>,
+    loading: Vec<SourceFile>,
+    loaded: HashSet<PathBuf>,
+}
+
+impl Modules {
+    /// 検索パスを返す
+    pub(crate) fn search_path(&self) -> &[PathBuf] {
+        &self.search_path
+    }
+
+    /// 検索パスを設定する
+    pub(crate) fn set_search_path(&mut self, search_path: Vec<PathBuf>) {
+        self.search_path = search_path;
+    }
+
+    /// 読み込み済みのファイルの正規化したパスを返す
+    pub(crate) fn loaded(&self) -> &HashSet<PathBuf> {
+        &self.loaded
+    }
+
+    /// 読み込み済みのファイルを設定する
+    pub(crate) fn set_loaded(&mut self, loaded: HashSet<PathBuf>) {
+        self.loaded = loaded;
+    }
+
+    /// 読み込み中のファイルのパスを返す
+    pub(crate) fn current_file(&self) -> Option<&Path> {
+        self.loading.last().map(|file| file.path.as_path())
+    }
+
+    /// 読み込み中のファイルのディレクトリ、検索パスの順に探したファイルのパスを返す
+    pub(crate) fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            return path.is_file().then(|| path.to_path_buf());
+        }
+
+        let base = match self.current_file() {
+            Some(file) => file.parent().unwrap_or(Path::new("")).to_path_buf(),
+            None => PathBuf::new(),
+        };
+        std::iter::once(base)
+            .chain(self.search_path.iter().cloned())
+            .map(|directory| directory.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+
+    /// ファイルの読み込みを開始する (一度だけ読み込む場合に読み込み済みなら `false` を返す)
+    pub(crate) fn enter(&mut self, path: &Path, once: bool) -> Result<bool, RuntimeError> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|error| RuntimeError::Io(format!("{}: {error}", path.display())))?;
+
+        if let Some(start) = self
+            .loading
+            .iter()
+            .position(|file| file.canonical == canonical)
+        {
+            let mut chain: Vec<String> = self.loading[start..]
+                .iter()
+                .map(|file| file.path.display().to_string())
+                .collect();
+            chain.push(path.display().to_string());
+            return Err(RuntimeError::ImportCycle(chain));
+        }
+        if once && self.loaded.contains(&canonical) {
+            return Ok(false);
+        }
+
+        self.loading.push(SourceFile {
+            path: path.to_path_buf(),
+            canonical,
+        });
+        Ok(true)
+    }
+
+    /// 最後に開始したファイルの読み込みを終了する (`succeeded` の場合は読み込み済みにする)
+    pub(crate) fn exit(&mut self, succeeded: bool) {
+        if let Some(file) = self.loading.pop() {
+            if succeeded {
+                self.loaded.insert(file.canonical);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::virtual_machine::{Limits, OutputBuffer, RuntimeError, VirtualMachine};
+
+    /// テストごとに作成したディレクトリ (破棄するときに中のファイルごと削除する)
+    struct Fixture(PathBuf);
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// テストごとのディレクトリにファイルを作成する
+    fn create_files(name: &str, files: &[(&str, &str)]) -> Fixture {
+        let fixture = Fixture(
+            std::env::temp_dir().join(format!("rustack-module-{name}-{}", std::process::id())),
+        );
+        for (path, source) in files {
+            let path = fixture.0.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        fixture
+    }
+
+    fn run(directory: &Path, search_path: Vec<PathBuf>) -> (String, Result<(), RuntimeError>) {
+        let output = OutputBuffer::new();
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_output(Box::new(output.clone()));
+        virtual_machine.set_search_path(search_path);
+        let result = virtual_machine.run_file(directory.join("main.txt"));
+        (output.text(), result)
+    }
+
+    #[test]
+    fn test_import() {
+        let fixture = create_files(
+            "import",
+            &[
+                (
+                    "main.txt",
+                    "\"lib/geometry.txt\" import\n\"lib/geometry.txt\" import\n\"shared.txt\" import\n3 square puts\n2 double puts",
+                ),
+                ("lib/geometry.txt", "\"loading\" =\n\"util.txt\" import\n/square { dup * } def"),
+                ("lib/util.txt", "/double { 2 * } def"),
+                ("shared/shared.txt", "\"shared\" ="),
+            ],
+        );
+        let directory = &fixture.0;
+        let (text, result) = run(directory, vec![directory.join("shared")]);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(text, "loading\nshared\n9\n4\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        let fixture = create_files(
+            "errors",
+            &[
+                ("main.txt", "1\n\"a.txt\" import"),
+                ("a.txt", "\n\"b.txt\" import"),
+                ("b.txt", "\"a.txt\" import"),
+            ],
+        );
+        let directory = &fixture.0;
+        let (_, result) = run(directory, vec![]);
+        let main = directory.join("main.txt").display().to_string();
+        let a = directory.join("a.txt").display().to_string();
+        let b = directory.join("b.txt").display().to_string();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            format!(
+                "{main}:2: cannot import \"a.txt\": {a}:2: cannot import \"b.txt\": \
+                 {b}:1: cannot import \"a.txt\": import cycle: {a} -> {b} -> {a}"
+            )
+        );
+
+        let mut virtual_machine = VirtualMachine::new();
+        assert_eq!(
+            virtual_machine.eval_str("\n\"missing.txt\" import"),
+            Err(RuntimeError::Import {
+                site: "<input>:2".to_string(),
+                path: "missing.txt".to_string(),
+                reason: Box::new(RuntimeError::ModuleNotFound("missing.txt".to_string())),
+            })
+        );
+    }
+
+    #[test]
+    fn test_import_after_error() {
+        let fixture = create_files("retry", &[("lib.txt", "/x 1 def undefined")]);
+        let path = fixture.0.join("lib.txt");
+        let import = format!("\"{}\" import", path.display());
+        let mut virtual_machine = VirtualMachine::new();
+
+        assert!(virtual_machine.eval_str(&import).is_err());
+        std::fs::write(&path, "/x 1 def /y 2 def").unwrap();
+        assert_eq!(virtual_machine.eval_str(&import), Ok(()));
+        assert_eq!(virtual_machine.call("y", []), Ok(vec![2.into()]));
+        assert_eq!(virtual_machine.eval_str(&import), Ok(()));
+    }
+
+    #[test]
+    fn test_import_passes_through_limits() {
+        let fixture = create_files("limits", &[("loop.txt", "{ 1 } { } while")]);
+        let import = format!("\"{}\" import", fixture.0.join("loop.txt").display());
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_limits(Limits {
+            instructions: Some(1_000),
+            ..Limits::unlimited()
+        });
+
+        assert_eq!(
+            virtual_machine.eval_str(&import),
+            Err(RuntimeError::InstructionLimitExceeded { limit: 1_000 })
+        );
+    }
+
+    #[test]
+    fn test_namespace() {
+        let output = OutputBuffer::new();
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_output(Box::new(output.clone()));
+        let source = "/geometry module
+  /square { dup * } def
+  /cube { dup square * } def
+  /square export
+  /cube export
+end
+/square { pop 0 } def
+3 geometry.cube puts
+3 square puts
+/geometry /square use
+3 square puts";
+
+        assert_eq!(virtual_machine.eval_str(source), Ok(()));
+        assert_eq!(output.text(), "27\n0\n9\n");
+        assert_eq!(virtual_machine.stack().dictionaries().len(), 1);
+    }
+
+    #[test]
+    fn test_namespace_errors() {
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine
+            .eval_str("/m module /secret { 1 } def /open { secret } def /open export end")
+            .unwrap();
+
+        assert_eq!(virtual_machine.call("m.open", []), Ok(vec![1.into()]));
+        assert_eq!(
+            virtual_machine.call("m.secret", []),
+            Err(RuntimeError::NotExported("m.secret".to_string()))
+        );
+        assert_eq!(
+            virtual_machine.call("secret", []),
+            Err(RuntimeError::UndefinedWord("secret".to_string()))
+        );
+        assert_eq!(
+            virtual_machine.eval_str("/m /secret use"),
+            Err(RuntimeError::NotExported("m.secret".to_string()))
+        );
+        assert_eq!(
+            virtual_machine.eval_str("end"),
+            Err(RuntimeError::Custom("end without module".to_string()))
+        );
+        assert_eq!(
+            virtual_machine.eval_str("/n module /missing export end"),
+            Err(RuntimeError::UndefinedWord("n.missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_namespace_in_word() {
+        let mut virtual_machine = VirtualMachine::new();
+
+        assert_eq!(
+            virtual_machine.eval_str("/f { /m module /x 1 def } def f"),
+            Err(RuntimeError::Custom(
+                "module must end in the word where it begins".to_string()
+            ))
+        );
+        assert_eq!(virtual_machine.stack().dictionaries().len(), 1);
+        assert_eq!(
+            virtual_machine.eval_str("/g { /m module /x 1 def /x export end } def g m.x"),
+            Ok(())
+        );
+        assert_eq!(virtual_machine.stack().list(), &vec![1.into()]);
+    }
+
+    #[test]
+    fn test_namespace_without_end() {
+        let fixture = create_files("unfinished", &[("g.txt", "/g module\n/x 1 def")]);
+        let include = format!("\"{}\" include", fixture.0.join("g.txt").display());
+        let mut virtual_machine = VirtualMachine::new();
+
+        assert!(matches!(
+            virtual_machine.eval_str(&include),
+            Err(RuntimeError::Import { reason, .. })
+                if *reason == RuntimeError::Custom("module without end".to_string())
+        ));
+        assert_eq!(
+            virtual_machine.eval_str("/h module /y 2 def"),
+            Err(RuntimeError::Custom("module without end".to_string()))
+        );
+        assert_eq!(virtual_machine.eval_str("/later 5 def"), Ok(()));
+        assert_eq!(virtual_machine.stack().dictionaries().len(), 1);
+        assert!(virtual_machine.stack().dictionaries()[0].contains_key("later"));
+    }
+
+    #[test]
+    fn test_namespace_discarded_on_error() {
+        let mut virtual_machine = VirtualMachine::new();
+
+        assert_eq!(
+            virtual_machine.eval_str("/m module\n/x 1 def undefined"),
+            Err(RuntimeError::UndefinedWord("undefined".to_string()))
+        );
+        assert_eq!(virtual_machine.eval_str("/z 5 def"), Ok(()));
+        assert_eq!(virtual_machine.stack().dictionaries().len(), 1);
+        assert!(virtual_machine.stack().dictionaries()[0].contains_key("z"));
+        assert_eq!(
+            virtual_machine.eval_str("end"),
+            Err(RuntimeError::Custom("end without module".to_string()))
+        );
+    }
+}
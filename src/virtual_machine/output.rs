@@ -0,0 +1,161 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::element::Element;
+
+/// `puts` などで出力した内容の出力先
+pub trait OutputSink {
+    /// 文字列を出力する
+    fn write_str(&mut self, text: &str) -> std::io::Result<()>;
+
+    /// `puts` で要素を出力する (出力用の文字列に改行を付けて出力する)
+    fn write_element(&mut self, element: &Element) -> std::io::Result<()> {
+        self.write_str(&format!("{}\n", element.to_text()))
+    }
+}
+
+impl<F> OutputSink for F
+where
+    F: FnMut(&str),
+{
+    fn write_str(&mut self, text: &str) -> std::io::Result<()> {
+        self(text);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for dyn OutputSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OutputSink")
+    }
+}
+
+/// 標準出力に出力する
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn write_str(&mut self, text: &str) -> std::io::Result<()> {
+        print!("{text}");
+        std::io::stdout().flush()
+    }
+}
+
+/// [`Write`] を実装した出力先に書き出す
+#[derive(Debug)]
+pub struct WriteSink<W>(pub W);
+
+impl<W: Write> OutputSink for WriteSink<W> {
+    fn write_str(&mut self, text: &str) -> std::io::Result<()> {
+        self.0.write_all(text.as_bytes())
+    }
+}
+
+/// 出力した要素と文字列をメモリに保持する (複製したものは内容を共有する)
+#[derive(Debug, Default, Clone)]
+pub struct OutputBuffer {
+    contents: Rc<RefCell<OutputContents>>,
+}
+
+#[derive(Debug, Default)]
+struct OutputContents {
+    elements: Vec<Element>,
+    text: String,
+}
+
+impl OutputBuffer {
+    /// 空の出力先を生成する
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `puts` で出力した要素を返す
+    pub fn elements(&self) -> Vec<Element> {
+        self.contents.borrow().elements.clone()
+    }
+
+    /// 出力した文字列を返す
+    pub fn text(&self) -> String {
+        self.contents.borrow().text.clone()
+    }
+
+    /// 出力した内容を消去する
+    pub fn clear(&self) {
+        let mut contents = self.contents.borrow_mut();
+        contents.elements.clear();
+        contents.text.clear();
+    }
+}
+
+impl OutputSink for OutputBuffer {
+    fn write_str(&mut self, text: &str) -> std::io::Result<()> {
+        self.contents.borrow_mut().text.push_str(text);
+        Ok(())
+    }
+
+    fn write_element(&mut self, element: &Element) -> std::io::Result<()> {
+        self.contents.borrow_mut().elements.push(element.clone());
+        self.write_str(&format!("{}\n", element.to_text()))
+    }
+}
+
+/// トレーサーやデバッガーの出力も同じ文字列に書き込めるようにする
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let text = std::str::from_utf8(buf)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+        self.contents.borrow_mut().text.push_str(text);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{OutputBuffer, OutputSink, WriteSink};
+    use crate::element::Element;
+    use crate::virtual_machine::VirtualMachine;
+
+    #[test]
+    fn test_buffer() {
+        let buffer = OutputBuffer::new();
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_output(Box::new(buffer.clone()));
+
+        virtual_machine
+            .eval_str("1 2 + puts 7 puts { 1 } puts")
+            .unwrap();
+
+        assert_eq!(
+            buffer.elements()[..2],
+            [Element::Number(3.0), Element::Integer(7)]
+        );
+        assert_eq!(buffer.text(), "3\n7\n{ 1 }\n");
+    }
+
+    #[test]
+    fn test_callback_and_writer() {
+        let texts = Rc::new(RefCell::new(vec![]));
+        let captured = texts.clone();
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_output(Box::new(move |text: &str| {
+            captured.borrow_mut().push(text.to_string())
+        }));
+        virtual_machine.eval_str("1.5 puts /x puts").unwrap();
+
+        assert_eq!(*texts.borrow(), vec!["1.5\n", "/x\n"]);
+
+        let mut sink = WriteSink(vec![]);
+        sink.write_element(&Element::String("text".to_string()))
+            .unwrap();
+        assert_eq!(sink.0, b"text\n");
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// 計測対象の種類
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ProfileKind {
+    /// ユーザー定義の語
+    Word,
+    /// 組み込みの演算
+    Native,
+}
+
+/// 語ごとの計測結果
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProfileEntry {
+    pub name: String,
+    pub kind: ProfileKind,
+    /// 呼び出し回数
+    pub calls: u64,
+    /// 呼び出した語を含めた時間 (再帰呼び出しは重複して数えない)
+    pub inclusive_time: Duration,
+    /// 呼び出した語を除いた時間
+    pub exclusive_time: Duration,
+    /// 呼び出した語を含めて評価した要素の数 (再帰呼び出しは重複して数えない)
+    pub inclusive_steps: u64,
+    /// 呼び出した語を除いて評価した要素の数
+    pub exclusive_steps: u64,
+}
+
+/// 呼び出し中の語
+#[derive(Debug)]
+struct Frame {
+    name: String,
+    started_at: Instant,
+    started_step: u64,
+    children_time: Duration,
+    children_steps: u64,
+}
+
+/// 語と組み込みの演算ごとに呼び出し回数、時間、評価した要素の数を計測するプロファイラ
+#[derive(Debug, Default)]
+pub struct Profiler {
+    frames: Vec<Frame>,
+    entries: HashMap<String, ProfileEntry>,
+    /// 呼び出し経路ごとの呼び出した語を除いて評価した要素の数
+    folded: HashMap<String, u64>,
+}
+
+impl Profiler {
+    /// プロファイラを生成する
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 語の呼び出しを開始する
+    pub(crate) fn enter(&mut self, name: &str, kind: ProfileKind, step: u64) {
+        self.entries
+            .entry(name.to_string())
+            .or_insert_with(|| ProfileEntry {
+                name: name.to_string(),
+                kind,
+                calls: 0,
+                inclusive_time: Duration::ZERO,
+                exclusive_time: Duration::ZERO,
+                inclusive_steps: 0,
+                exclusive_steps: 0,
+            })
+            .calls += 1;
+
+        self.frames.push(Frame {
+            name: name.to_string(),
+            started_at: Instant::now(),
+            started_step: step,
+            children_time: Duration::ZERO,
+            children_steps: 0,
+        });
+    }
+
+    /// 最後に開始した語の呼び出しを終了する
+    pub(crate) fn exit(&mut self, step: u64) {
+        let path = self.path();
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        let time = frame.started_at.elapsed();
+        let steps = step - frame.started_step;
+        let exclusive_steps = steps - frame.children_steps;
+        let recursive = self.frames.iter().any(|outer| outer.name == frame.name);
+
+        let entry = self.entries.get_mut(&frame.name).unwrap();
+        entry.exclusive_time += time.saturating_sub(frame.children_time);
+        entry.exclusive_steps += exclusive_steps;
+        if !recursive {
+            entry.inclusive_time += time;
+            entry.inclusive_steps += steps;
+        }
+
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children_time += time;
+            parent.children_steps += steps;
+        }
+
+        *self.folded.entry(path).or_default() += exclusive_steps;
+    }
+
+    /// 呼び出し中の語を `;` でつないだ経路を返す
+    fn path(&self) -> String {
+        let names: Vec<&str> = self
+            .frames
+            .iter()
+            .map(|frame| frame.name.as_str())
+            .collect();
+        names.join(";")
+    }
+
+    /// 呼び出した語を除いた時間の長い順に計測結果を返す
+    pub fn entries(&self) -> Vec<&ProfileEntry> {
+        let mut entries: Vec<&ProfileEntry> = self.entries.values().collect();
+        entries.sort_by(|lhs, rhs| {
+            rhs.exclusive_time
+                .cmp(&lhs.exclusive_time)
+                .then(rhs.exclusive_steps.cmp(&lhs.exclusive_steps))
+                .then(lhs.name.cmp(&rhs.name))
+        });
+        entries
+    }
+
+    /// 計測結果を表形式で書き出す
+    pub fn write_report(&self, output: &mut impl Write) -> std::io::Result<()> {
+        writeln!(
+            output,
+            "{:<20} {:<6} {:>10} {:>12} {:>12} {:>12} {:>12}",
+            "name", "kind", "calls", "incl ms", "excl ms", "incl steps", "excl steps"
+        )?;
+        for entry in self.entries() {
+            let kind = match entry.kind {
+                ProfileKind::Word => "word",
+                ProfileKind::Native => "native",
+            };
+            writeln!(
+                output,
+                "{:<20} {:<6} {:>10} {:>12.3} {:>12.3} {:>12} {:>12}",
+                entry.name,
+                kind,
+                entry.calls,
+                entry.inclusive_time.as_secs_f64() * 1000.0,
+                entry.exclusive_time.as_secs_f64() * 1000.0,
+                entry.inclusive_steps,
+                entry.exclusive_steps,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// 評価した要素の数を重みとして flamegraph 向けの folded stack 形式で書き出す
+    pub fn write_folded(&self, output: &mut impl Write) -> std::io::Result<()> {
+        let mut paths: Vec<(&String, &u64)> = self.folded.iter().collect();
+        paths.sort();
+        for (path, steps) in paths {
+            writeln!(output, "{path} {steps}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProfileKind, Profiler};
+    use crate::virtual_machine::VirtualMachine;
+
+    fn profile(source: &str) -> Profiler {
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_profiler(Some(Profiler::new()));
+        virtual_machine.eval_str(source).unwrap();
+        virtual_machine.take_profiler().unwrap()
+    }
+
+    #[test]
+    fn test_counts() {
+        let profiler = profile("/double { 2 * } def\n/quad { double double } def\n1 quad");
+        let entries = profiler.entries();
+        let find = |name: &str| entries.iter().find(|entry| entry.name == name).unwrap();
+
+        assert_eq!(find("quad").kind, ProfileKind::Word);
+        assert_eq!(find("quad").calls, 1);
+        assert_eq!(find("quad").inclusive_steps, 6);
+        assert_eq!(find("quad").exclusive_steps, 2);
+        assert_eq!(find("double").calls, 2);
+        assert_eq!(find("double").inclusive_steps, 4);
+        assert_eq!(find("double").exclusive_steps, 4);
+        assert_eq!(find("*").kind, ProfileKind::Native);
+        assert_eq!(find("*").calls, 2);
+        assert_eq!(find("def").calls, 2);
+    }
+
+    #[test]
+    fn test_recursion() {
+        let profiler = profile("/f { { dup 0 exch < } { 1 - f } { } if } def\n2 f");
+        let entries = profiler.entries();
+        let f = entries.iter().find(|entry| entry.name == "f").unwrap();
+
+        assert_eq!(f.calls, 3);
+        assert!(f.inclusive_steps >= f.exclusive_steps);
+        assert_eq!(f.inclusive_steps, profiler_total_steps(&profiler, "f"));
+    }
+
+    /// 経路の先頭が指定した語である要素の数を合計する
+    fn profiler_total_steps(profiler: &Profiler, name: &str) -> u64 {
+        let mut folded = vec![];
+        profiler.write_folded(&mut folded).unwrap();
+        String::from_utf8(folded)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with(name))
+            .map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+            .sum()
+    }
+
+    #[test]
+    fn test_folded() {
+        let profiler = profile("/double { 2 * } def\n/quad { double double } def\n1 quad");
+        let mut folded = vec![];
+        profiler.write_folded(&mut folded).unwrap();
+
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "def 0\nquad 2\nquad;double 4\nquad;double;* 0\n"
+        );
+    }
+}
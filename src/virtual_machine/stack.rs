@@ -1,216 +1,772 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
-use crate::element::{Element, NativeOperation};
+use crate::element::{Block, Element, NativeOperation};
+use crate::parser::Parser;
 
-use super::stack_helper::{impl_operation, impl_operation_integer};
+use super::debugger::Debugger;
+use super::error::RuntimeError;
+use super::input::{InputSource, StdinSource};
+use super::interrupt::InterruptHandle;
+use super::limits::{Limits, Usage};
+use super::module::{Modules, Namespace, OpenNamespace};
+use super::output::{OutputSink, StdoutSink};
+use super::profiler::{ProfileKind, Profiler};
+use super::stack_helper::builtins;
+use super::tracer::TraceHook;
 
 /// スタック
 #[derive(Debug)]
 pub struct Stack {
     list: Vec<Element>,
     variables: Vec<HashMap<String, Element>>,
-    outputs: Vec<f32>,
+    output: Box<dyn OutputSink>,
+    input: Box<dyn InputSource>,
+    written: usize,
+    steps: u64,
+    /// 実行を開始したときの `steps` (命令数の上限はここから数える)
+    run_start: u64,
+    limits: Limits,
+    interrupt: InterruptHandle,
+    call_stack: Vec<String>,
+    line: usize,
+    debugger: Option<Debugger>,
+    tracer: Option<Box<dyn TraceHook>>,
+    profiler: Option<Profiler>,
+    modules: Modules,
+    namespaces: HashMap<String, Namespace>,
+    open_namespaces: Vec<OpenNamespace>,
+    /// 実行中の語が属する名前空間
+    contexts: Vec<Option<String>>,
+    /// プログラムに渡すコマンドライン引数
+    args: Vec<String>,
+    /// スタックと辞書と名前空間に存在する要素の使用量 (要素を出し入れするたびに更新する)
+    usage: Usage,
+    /// これより奥の要素は取り出すことも参照することもできない (`VirtualMachine::call` で呼び出す前の要素を守る)
+    floor: usize,
+}
+
+/// スタックと定義された語を保存したもの
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    list: Vec<Element>,
+    variables: Vec<HashMap<String, Element>>,
+    namespaces: HashMap<String, Namespace>,
+    open_namespaces: Vec<OpenNamespace>,
+    /// `import` で読み込み済みのファイル
+    loaded: HashSet<PathBuf>,
+    usage: Usage,
 }
 
 impl Stack {
     /// スタックを生成する
     pub fn new() -> Self {
-        let functions: [(&str, fn(&mut Stack)); 14] = [
-            ("+", Stack::add),
-            ("-", Stack::subtract),
-            ("*", Stack::multiply),
-            ("/", Stack::divide),
-            ("<", Stack::less_than),
-            ("if", Stack::operate_if),
-            ("def", Stack::operate_define),
-            ("for", Stack::operate_for),
-            ("while", Stack::operate_while),
-            ("puts", Stack::puts),
-            ("pop", Stack::pop),
-            ("dup", Stack::duplicate),
-            ("exch", Stack::exchange),
-            ("index", Stack::index),
-        ];
+        let globals: HashMap<String, Element> = builtins()
+            .into_iter()
+            .map(|(name, native)| (name.to_string(), Element::NativeOperation(native)))
+            .collect();
         Self {
             list: vec![],
-            variables: vec![functions
-                .into_iter()
-                .map(|(name, function)| {
-                    (
-                        name.to_string(),
-                        Element::NativeOperation(NativeOperation(function)),
-                    )
-                })
-                .collect()],
-            outputs: vec![],
+            usage: Usage::of_dictionary(&globals),
+            variables: vec![globals],
+            output: Box::new(StdoutSink),
+            input: Box::new(StdinSource),
+            written: 0,
+            steps: 0,
+            run_start: 0,
+            limits: Limits::unlimited(),
+            interrupt: InterruptHandle::new(),
+            call_stack: vec![],
+            line: 0,
+            debugger: None,
+            tracer: None,
+            profiler: None,
+            modules: Modules::default(),
+            namespaces: HashMap::new(),
+            open_namespaces: vec![],
+            contexts: vec![],
+            args: vec![],
+            floor: 0,
         }
     }
 
-    pub fn outputs(&self) -> &Vec<f32> {
-        &self.outputs
+    /// 取り出せるスタックの深さの下限を設定し、元の下限を返す
+    pub(crate) fn set_floor(&mut self, floor: usize) -> usize {
+        std::mem::replace(&mut self.floor, floor)
+    }
+
+    /// 出力先を設定する
+    pub fn set_output(&mut self, output: Box<dyn OutputSink>) {
+        self.output = output;
+    }
+
+    /// 入力元を設定する
+    pub fn set_input(&mut self, input: Box<dyn InputSource>) {
+        self.input = input;
     }
 
     pub fn list(&self) -> &Vec<Element> {
         &self.list
     }
 
+    /// これまでに評価した要素の数を返す
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// 評価した要素の数を0に戻す
+    pub fn reset_steps(&mut self) {
+        self.steps = 0;
+        self.run_start = 0;
+    }
+
+    /// 実行を開始し、命令数の上限をここから数える
+    pub(crate) fn begin_run(&mut self) {
+        self.run_start = self.steps;
+    }
+
+    /// 評価できる要素の数の上限を設定する
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.limits.instructions = limit;
+    }
+
+    /// 資源の上限を返す
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// 資源の上限を設定する
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// プログラムに渡すコマンドライン引数を返す
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// プログラムに渡すコマンドライン引数を設定する
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// 実行を中断するためのハンドルを返す
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// 辞書スタックを返す
+    pub fn dictionaries(&self) -> &[HashMap<String, Element>] {
+        &self.variables
+    }
+
+    /// 呼び出し中の語の名前を呼び出し順に返す
+    pub fn call_stack(&self) -> &[String] {
+        &self.call_stack
+    }
+
+    /// 評価中の要素の行番号を返す (不明な場合は0)
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 読み込み中のファイルのパスを返す (ファイルから読み込んでいない場合は `None`)
+    pub fn current_file(&self) -> Option<&Path> {
+        self.modules.current_file()
+    }
+
+    /// 次に評価する要素の行番号を設定する
+    pub fn set_line(&mut self, line: usize) {
+        self.line = line;
+    }
+
+    /// デバッガを設定する
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        self.debugger = debugger;
+    }
+
+    /// デバッガを返す
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+
+    /// トレースのフックを設定する
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn TraceHook>>) {
+        self.tracer = tracer;
+    }
+
+    /// プロファイラを設定する
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    /// プロファイラを返す
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// プロファイラを取り外して返す
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    /// スタックと定義された語を保存する
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            list: self.list.clone(),
+            variables: self.variables.clone(),
+            namespaces: self.namespaces.clone(),
+            open_namespaces: self.open_namespaces.clone(),
+            loaded: self.modules.loaded().clone(),
+            usage: self.usage,
+        }
+    }
+
+    /// 保存したスタックと定義された語に戻す
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.list = snapshot.list;
+        self.variables = snapshot.variables;
+        self.namespaces = snapshot.namespaces;
+        self.open_namespaces = snapshot.open_namespaces;
+        self.modules.set_loaded(snapshot.loaded);
+        self.usage = snapshot.usage;
+        self.call_stack.clear();
+        self.contexts.clear();
+    }
+
     /// 要素を評価する
-    pub fn evaluate(&mut self, element: Element) {
+    pub fn evaluate(&mut self, element: Element) -> Result<(), RuntimeError> {
+        self.consume_step()?;
+
+        if let Some(mut debugger) = self.debugger.take() {
+            let result = debugger.before_evaluate(self, &element);
+            self.debugger = Some(debugger);
+            result?;
+        }
+
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.trace(self, &element);
+            self.tracer = Some(tracer);
+        }
+
         match element {
             Element::Operation(operation) => self.execute(operation),
-            Element::NativeOperation(_) => panic!("Native operation is not allowed!"),
+            Element::NativeOperation(native) => native.call(self),
             _ => self.push(element),
         }
     }
 
-    /// 複数の要素を評価する
-    fn evaluate_multiple(&mut self, elements: Vec<Element>) {
-        for element in elements {
-            self.evaluate(element);
+    /// ブロックの中身を順に評価する
+    pub fn evaluate_block(&mut self, block: &Block) -> Result<(), RuntimeError> {
+        for (element, line) in block.iter() {
+            self.line = line;
+            self.evaluate(element.clone())?;
         }
+        Ok(())
+    }
+
+    /// 中断の要求を確認して命令数を1つ消費する
+    fn consume_step(&mut self) -> Result<(), RuntimeError> {
+        if self.interrupt.take() {
+            return Err(RuntimeError::Interrupted);
+        }
+        if let Some(limit) = self.limits.instructions {
+            if self.steps - self.run_start >= limit {
+                return Err(RuntimeError::InstructionLimitExceeded { limit });
+            }
+        }
+        self.steps += 1;
+        Ok(())
     }
 
     /// スタックに要素を入れる
-    fn push(&mut self, element: Element) {
+    pub fn push(&mut self, element: impl Into<Element>) -> Result<(), RuntimeError> {
+        if let Some(limit) = self.limits.stack_depth {
+            if self.list.len() >= limit {
+                return Err(RuntimeError::StackDepthExceeded { limit });
+            }
+        }
+        let element = element.into();
+        let mut usage = self.usage;
+        usage += Usage::of_element(&element);
+        self.check_memory(usage)?;
+        self.usage = usage;
         self.list.push(element);
+        Ok(())
     }
 
-    /// 変数を見つける
-    fn find_variable(&self, name: &str) -> Option<Element> {
-        self.variables
-            .iter()
-            .rev()
-            .find_map(|vars| vars.get(name).map(|var| var.to_owned()))
+    /// スタックの先頭を取り出す
+    pub fn pop(&mut self) -> Result<Element, RuntimeError> {
+        if self.list.len() <= self.floor {
+            return Err(RuntimeError::StackUnderflow);
+        }
+        let element = self.list.pop().ok_or(RuntimeError::StackUnderflow)?;
+        self.usage -= Usage::of_element(&element);
+        Ok(element)
     }
 
-    /// 演算を実行する
-    fn execute(&mut self, operation: String) {
-        let element = self
-            .find_variable(&operation)
-            .expect(&format!("{operation:?} is undefined"))
-            .clone();
+    /// スタックの先頭からn個の要素をスタックの奥から順に取り出す
+    pub fn pop_many(&mut self, n: usize) -> Result<Vec<Element>, RuntimeError> {
+        let start = self
+            .list
+            .len()
+            .checked_sub(n)
+            .filter(|start| *start >= self.floor)
+            .ok_or(RuntimeError::StackUnderflow)?;
+        let elements = self.list.split_off(start);
+        for element in &elements {
+            self.usage -= Usage::of_element(element);
+        }
+        Ok(elements)
+    }
 
-        match element {
-            Element::Block(block) => {
-                self.variables.push(HashMap::new());
-                self.evaluate_multiple(block.to_vec());
-                self.variables.pop();
+    /// スタックの先頭を整数として取り出す (数値は小数点以下を切り捨てる)
+    pub fn pop_integer(&mut self) -> Result<i32, RuntimeError> {
+        self.pop()?.try_into()
+    }
+
+    /// スタックの先頭を数値として取り出す (整数は数値にする)
+    pub fn pop_number(&mut self) -> Result<f32, RuntimeError> {
+        self.pop()?.try_into()
+    }
+
+    /// スタックの先頭を文字列として取り出す
+    pub fn pop_string(&mut self) -> Result<String, RuntimeError> {
+        self.pop()?.try_into()
+    }
+
+    /// スタックの先頭をブロックとして取り出す
+    pub fn pop_block(&mut self) -> Result<Block, RuntimeError> {
+        self.pop()?.try_into()
+    }
+
+    /// スタックの先頭をシンボルとして取り出し、その名前を返す
+    pub fn pop_symbol(&mut self) -> Result<String, RuntimeError> {
+        match self.pop()? {
+            Element::Symbol(name) => Ok(name),
+            found => Err(RuntimeError::TypeMismatch {
+                expected: "symbol",
+                found,
+            }),
+        }
+    }
+
+    /// 先頭からn番目 (先頭は0) の要素を返す
+    pub fn peek(&self, n: usize) -> Result<&Element, RuntimeError> {
+        self.list
+            .len()
+            .checked_sub(n + 1)
+            .filter(|index| *index >= self.floor)
+            .map(|index| &self.list[index])
+            .ok_or(RuntimeError::StackUnderflow)
+    }
+
+    /// 変更後の使用量が上限以内か確認する (上限を超える場合は変更する前に失敗させる)
+    fn check_memory(&self, usage: Usage) -> Result<(), RuntimeError> {
+        if let Some(limit) = self.limits.elements {
+            if usage.elements > limit {
+                return Err(RuntimeError::ElementLimitExceeded { limit });
             }
-            Element::NativeOperation(operation) => (operation.0)(self),
-            _ => self.push(element),
         }
+        if let Some(limit) = self.limits.bytes {
+            if usage.bytes > limit {
+                return Err(RuntimeError::MemoryLimitExceeded { limit });
+            }
+        }
+        Ok(())
     }
 
-    // 加算を行う
-    impl_operation!(add, +);
+    /// スタックと辞書と名前空間の要素をすべて数えて使用量を求める
+    #[cfg(test)]
+    fn measure(&self) -> Usage {
+        let mut usage = Usage::default();
+        for element in &self.list {
+            usage += Usage::of_element(element);
+        }
+        for dictionary in &self.variables {
+            usage += Usage::of_dictionary(dictionary);
+        }
+        for namespace in self.namespaces.values() {
+            usage += Usage::of_dictionary(&namespace.words);
+        }
+        usage
+    }
 
-    // 減算を行う
-    impl_operation!(subtract, -);
+    /// 現在の辞書に変数を定義する
+    pub fn define(&mut self, name: &str, element: Element) -> Result<(), RuntimeError> {
+        let mut usage = self.usage;
+        usage += Usage::of_entry(name, &element);
+        if let Some(replaced) = self.variables.last().unwrap().get(name) {
+            usage -= Usage::of_entry(name, replaced);
+        }
+        self.check_memory(usage)?;
+        self.usage = usage;
+        self.variables
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), element);
+        Ok(())
+    }
 
-    // 乗算を行う
-    impl_operation!(multiply, *);
+    /// 要素を出力先に出力する
+    pub fn write_element(&mut self, element: &Element) -> Result<(), RuntimeError> {
+        self.count_output()?;
+        self.output
+            .write_element(element)
+            .map_err(|error| RuntimeError::Io(error.to_string()))
+    }
 
-    // 除算を行う
-    impl_operation!(divide, /);
+    /// 文字列を出力先に出力する
+    pub fn write_str(&mut self, text: &str) -> Result<(), RuntimeError> {
+        self.count_output()?;
+        self.output
+            .write_str(text)
+            .map_err(|error| RuntimeError::Io(error.to_string()))
+    }
 
-    // 小なり大小比較を行う
-    impl_operation_integer!(less_than, <);
+    /// `import` でファイルを探すディレクトリを返す
+    pub fn search_path(&self) -> &[PathBuf] {
+        self.modules.search_path()
+    }
 
-    /// 条件分岐を行う
-    fn operate_if(&mut self) {
-        let false_branch = self.list.pop().unwrap().to_block_vec();
-        let true_branch = self.list.pop().unwrap().to_block_vec();
-        let condition = self.list.pop().unwrap().to_block_vec();
+    /// `import` でファイルを探すディレクトリを設定する
+    pub fn set_search_path(&mut self, search_path: Vec<PathBuf>) {
+        self.modules.set_search_path(search_path);
+    }
 
-        self.evaluate_multiple(condition);
+    /// ファイルを読み込んで評価する (読み込み中のファイルからの相対パスで `import` できる)
+    pub fn run_file(&mut self, path: &Path) -> Result<(), RuntimeError> {
+        self.load(path, false)
+    }
+
+    /// 読み込み中のファイルのディレクトリと検索パスからファイルを探して評価する
+    ///
+    /// `once` の場合は読み込み済みのファイルを評価しない。エラーには読み込みを行った位置を付ける。
+    pub fn import(&mut self, path: &str, once: bool) -> Result<(), RuntimeError> {
+        let result = match self.modules.resolve(path) {
+            Some(resolved) => self.load(&resolved, once),
+            None => Err(RuntimeError::ModuleNotFound(path.to_string())),
+        };
+        result.map_err(|reason| {
+            // `exit` や中断、資源の上限は読み込んだファイルの中で発生してもそのまま返す
+            if reason.stops_execution() {
+                return reason;
+            }
+            let file = self
+                .modules
+                .current_file()
+                .map_or(String::from("<input>"), |file| file.display().to_string());
+            RuntimeError::Import {
+                site: format!("{file}:{}", self.line),
+                path: path.to_string(),
+                reason: Box::new(reason),
+            }
+        })
+    }
+
+    /// ファイルを評価し、評価後に行番号を戻す
+    ///
+    /// 読み込み済みかは読む前に確認し、評価に成功したファイルだけを読み込み済みにする。
+    fn load(&mut self, path: &Path, once: bool) -> Result<(), RuntimeError> {
+        if !self.modules.enter(path, once)? {
+            return Ok(());
+        }
+
+        let line = self.line;
+        let depth = self.variables.len();
+        let result = match std::fs::read_to_string(path) {
+            Ok(source) => {
+                let mut parser = Parser::new();
+                source
+                    .lines()
+                    .try_for_each(|text| {
+                        parser.parse(text.to_string()).try_for_each(|element| {
+                            self.line = parser.line();
+                            self.evaluate(element)
+                        })
+                    })
+                    .and_then(|()| self.finish_source(&parser, depth))
+            }
+            Err(error) => Err(RuntimeError::Io(format!("{}: {error}", path.display()))),
+        };
+        self.modules.exit(result.is_ok());
+        self.line = line;
+        result
+    }
 
-        let condition_result = self.list.pop().unwrap().as_integer();
+    /// 入力元から1行読み込む (入力の終わりでは `None` を返す)
+    pub fn read_line(&mut self) -> Result<Option<String>, RuntimeError> {
+        self.input
+            .read_line()
+            .map_err(|error| RuntimeError::Io(error.to_string()))
+    }
+
+    /// 入力元の残りをすべて読み込む
+    pub fn read_all(&mut self) -> Result<String, RuntimeError> {
+        self.input
+            .read_all()
+            .map_err(|error| RuntimeError::Io(error.to_string()))
+    }
+
+    /// 出力の回数が上限以内か確認して1つ数える
+    fn count_output(&mut self) -> Result<(), RuntimeError> {
+        if let Some(limit) = self.limits.outputs {
+            if self.written >= limit {
+                return Err(RuntimeError::OutputLimitExceeded { limit });
+            }
+        }
+        self.written += 1;
+        Ok(())
+    }
 
-        match condition_result {
-            0 => self.evaluate_multiple(false_branch),
-            _ => self.evaluate_multiple(true_branch),
+    /// 組み込みの演算を大域の辞書に登録する (同じ名前の語は上書きする)
+    pub fn register(
+        &mut self,
+        name: &str,
+        function: impl Fn(&mut Stack) -> Result<(), RuntimeError> + 'static,
+    ) {
+        self.register_operation(name, NativeOperation::new(function));
+    }
+
+    /// [`native_operation!`](crate::native_operation) などで定義した組み込みの演算を大域の辞書に登録する
+    pub fn register_operation(&mut self, name: &str, native: NativeOperation) {
+        let element = Element::NativeOperation(native);
+        self.usage += Usage::of_entry(name, &element);
+        if let Some(replaced) = self.variables[0].insert(name.to_string(), element) {
+            self.usage -= Usage::of_entry(name, &replaced);
         }
     }
 
-    /// 変数定義を行う
-    fn operate_define(&mut self) {
-        let element = self.list.pop().unwrap();
-        self.evaluate(element);
-        let element = self.list.pop().unwrap();
-        let symbol = self.list.pop().unwrap().as_symbol();
+    /// 大域の辞書から語を削除する
+    pub fn unregister(&mut self, name: &str) -> Option<Element> {
+        let removed = self.variables[0].remove(name)?;
+        self.usage -= Usage::of_entry(name, &removed);
+        Some(removed)
+    }
+
+    /// 名前空間の定義を開始する (定義した語は名前空間の辞書に入る)
+    pub fn begin_namespace(&mut self, name: &str) -> Result<(), RuntimeError> {
+        self.variables.push(HashMap::new());
+        self.open_namespaces.push(OpenNamespace {
+            name: name.to_string(),
+            depth: self.variables.len(),
+            exports: HashSet::new(),
+        });
+        Ok(())
+    }
 
-        self.variables.last_mut().unwrap().insert(symbol, element);
+    /// 定義中の名前空間の語を公開する
+    pub fn export(&mut self, name: &str) -> Result<(), RuntimeError> {
+        let namespace = self
+            .open_namespaces
+            .last_mut()
+            .ok_or_else(|| RuntimeError::Custom("export outside of module".to_string()))?;
+        namespace.exports.insert(name.to_string());
+        Ok(())
     }
 
-    /// for文による繰り返し操作を行う
-    fn operate_for(&mut self) {
-        let loop_block = self.list.pop().unwrap().to_block_vec();
-        let end = self.list.pop().unwrap().as_integer();
-        let start = self.list.pop().unwrap().as_integer();
+    /// 名前空間の定義を終了する
+    pub fn end_namespace(&mut self) -> Result<(), RuntimeError> {
+        match self.open_namespaces.last() {
+            Some(namespace) if namespace.depth == self.variables.len() => {}
+            Some(_) => {
+                return Err(RuntimeError::Custom(
+                    "module must end in the word where it begins".to_string(),
+                ))
+            }
+            None => return Err(RuntimeError::Custom("end without module".to_string())),
+        }
 
-        for _ in start..=end {
-            self.evaluate_multiple(loop_block.clone());
+        let namespace = self.open_namespaces.pop().unwrap();
+        let words = self.variables.pop().unwrap();
+        if let Some(name) = namespace
+            .exports
+            .iter()
+            .find(|name| !words.contains_key(*name))
+        {
+            self.usage -= Usage::of_dictionary(&words);
+            return Err(RuntimeError::UndefinedWord(format!(
+                "{}.{name}",
+                namespace.name
+            )));
+        }
+        let replaced = self.namespaces.insert(
+            namespace.name,
+            Namespace {
+                words,
+                exports: namespace.exports,
+            },
+        );
+        if let Some(replaced) = replaced {
+            self.usage -= Usage::of_dictionary(&replaced.words);
         }
+        Ok(())
     }
 
-    /// while文による繰り返し操作を行う
-    fn operate_while(&mut self) {
-        let loop_block = self.list.pop().unwrap().to_block_vec();
-        let condition = self.list.pop().unwrap().to_block_vec();
-        self.evaluate_multiple(condition.clone());
-        let mut condition_result = self.list.pop().unwrap().as_integer();
+    /// 辞書スタックの深さ `depth` より後に開いて終わっていない名前空間の定義を破棄する (破棄したかを返す)
+    pub(crate) fn discard_namespaces(&mut self, depth: usize) -> bool {
+        let count = self
+            .open_namespaces
+            .iter()
+            .filter(|namespace| namespace.depth > depth)
+            .count();
+        if count == 0 {
+            return false;
+        }
+        self.open_namespaces
+            .truncate(self.open_namespaces.len() - count);
+        for words in self.variables.drain(depth..) {
+            self.usage -= Usage::of_dictionary(&words);
+        }
+        true
+    }
 
-        while condition_result != 0 {
-            self.evaluate_multiple(loop_block.clone());
-            self.evaluate_multiple(condition.clone());
-            condition_result = self.list.pop().unwrap().as_integer();
+    /// ソースの終わりで閉じていないブロックや終わっていない名前空間があればエラーを返す
+    ///
+    /// `depth` はソースを評価する前の辞書スタックの深さで、ソースの中で始めて終わっていない名前空間は破棄する。
+    pub(crate) fn finish_source(
+        &mut self,
+        parser: &Parser,
+        depth: usize,
+    ) -> Result<(), RuntimeError> {
+        let unfinished = self.discard_namespaces(depth);
+        if let Some(line) = parser.open_block_line() {
+            return Err(RuntimeError::UnclosedBlock { line });
+        }
+        if unfinished {
+            return Err(RuntimeError::Custom("module without end".to_string()));
         }
+        Ok(())
     }
 
-    /// スタックの先頭を取り出して表示する
-    fn puts(&mut self) {
-        let element = self.list.pop().unwrap();
-        self.outputs.push(element.as_number());
+    /// 名前空間の公開された語を現在の辞書から名前空間なしで呼び出せるようにする
+    pub fn use_word(&mut self, namespace: &str, name: &str) -> Result<(), RuntimeError> {
+        let qualified = format!("{namespace}.{name}");
+        self.find_word(&qualified)?;
+        let alias = Block::from_vec(vec![Element::Operation(qualified)]);
+        self.define(name, Element::Block(alias))
     }
 
-    /// スタックの先頭を取り出す
-    fn pop(&mut self) {
-        self.list.pop().unwrap();
+    /// 語を評価せずに返す
+    pub fn lookup(&self, name: &str) -> Result<Element, RuntimeError> {
+        self.find_word(name).map(|(element, _)| element)
     }
 
-    /// スタックの先頭を複製する
-    fn duplicate(&mut self) {
-        let element = self.list.last().unwrap();
-        self.list.push(element.clone());
+    /// 語を見つけ、語と語が属する名前空間を返す
+    ///
+    /// 辞書スタックを内側から探し、実行中の語が属する名前空間、大域の辞書の順に探す。
+    /// `名前空間.語` の形式の場合は名前空間の公開された語を探す。
+    fn find_word(&self, name: &str) -> Result<(Element, Option<String>), RuntimeError> {
+        let context = self.contexts.last().cloned().flatten();
+        if let Some(element) = self.variables[1..]
+            .iter()
+            .rev()
+            .find_map(|variables| variables.get(name))
+        {
+            return Ok((element.clone(), context));
+        }
+        if let Some(element) = context
+            .as_ref()
+            .and_then(|namespace| self.namespaces[namespace].words.get(name))
+        {
+            return Ok((element.clone(), context));
+        }
+        if let Some(element) = self.variables[0].get(name) {
+            return Ok((element.clone(), None));
+        }
+
+        let (namespace, word) = name
+            .rsplit_once('.')
+            .ok_or_else(|| RuntimeError::UndefinedWord(name.to_string()))?;
+        let element = self
+            .namespaces
+            .get(namespace)
+            .and_then(|module| module.words.get(word))
+            .ok_or_else(|| RuntimeError::UndefinedWord(name.to_string()))?;
+        if context.as_deref() != Some(namespace)
+            && !self.namespaces[namespace].exports.contains(word)
+        {
+            return Err(RuntimeError::NotExported(name.to_string()));
+        }
+        Ok((element.clone(), Some(namespace.to_string())))
     }
 
-    /// スタックの先頭と先頭から2番目を交換する
-    fn exchange(&mut self) {
-        let last = self.list.pop().unwrap();
-        let second = self.list.pop().unwrap();
-        self.list.push(last);
-        self.list.push(second);
+    /// 語を実行する
+    pub fn execute(&mut self, operation: String) -> Result<(), RuntimeError> {
+        let (element, namespace) = self.find_word(&operation)?;
+
+        match element {
+            Element::Block(block) => {
+                if let Some(limit) = self.limits.dictionary_depth {
+                    if self.variables.len() >= limit {
+                        return Err(RuntimeError::DictionaryDepthExceeded { limit });
+                    }
+                }
+                self.profile_enter(&operation, ProfileKind::Word);
+                self.variables.push(HashMap::new());
+                let depth = self.variables.len();
+                self.call_stack.push(operation);
+                self.contexts.push(namespace);
+                let mut result = self.evaluate_block(&block);
+                // 語の中で始めて終わっていない名前空間は語から戻るときに破棄する
+                if self.discard_namespaces(depth) {
+                    result = result.and(Err(RuntimeError::Custom(
+                        "module must end in the word where it begins".to_string(),
+                    )));
+                }
+                self.contexts.pop();
+                self.call_stack.pop();
+                let locals = self.variables.pop().unwrap();
+                self.usage -= Usage::of_dictionary(&locals);
+                self.profile_exit();
+                result
+            }
+            Element::NativeOperation(native) => {
+                self.profile_enter(&operation, ProfileKind::Native);
+                let result = native.call(self);
+                self.profile_exit();
+                result
+            }
+            _ => self.push(element),
+        }
     }
 
-    /// インデックス
-    fn index(&mut self) {
-        let index = self.list.pop().unwrap().as_integer() as usize;
-        let element = self.list[self.list.len() - index - 1].clone();
-        self.list.push(element);
+    /// プロファイラに語の呼び出しの開始を記録する
+    fn profile_enter(&mut self, name: &str, kind: ProfileKind) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.enter(name, kind, self.steps);
+        }
+    }
+
+    /// プロファイラに語の呼び出しの終了を記録する
+    fn profile_exit(&mut self) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.exit(self.steps);
+        }
+    }
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
     use super::Stack;
     use crate::element::tests::create_block;
     use crate::element::Element;
     use crate::parser::{Parser, ParserIterator};
+    use crate::virtual_machine::{Limits, OutputBuffer, RuntimeError};
 
     fn parse(parser: &mut ParserIterator) -> Stack {
         let mut stack = Stack::new();
         for element in parser {
-            stack.evaluate(element);
+            stack.evaluate(element).unwrap();
         }
         stack
     }
@@ -218,10 +774,10 @@ mod tests {
     #[test]
     fn test_add() {
         let mut stack = Stack::new();
-        stack.push(Element::Integer(45));
-        stack.push(Element::Integer(55));
+        stack.push(Element::Integer(45)).unwrap();
+        stack.push(Element::Integer(55)).unwrap();
 
-        stack.add();
+        stack.execute("+".to_string()).unwrap();
 
         assert_eq!(stack.list[0], Element::Number(100.0));
     }
@@ -292,7 +848,7 @@ if
 "#;
         for line in lines.lines() {
             for element in parser.parse(line.to_string()) {
-                stack.evaluate(element);
+                stack.evaluate(element).unwrap();
             }
         }
 
@@ -310,7 +866,7 @@ if
 
         for line in lines.lines() {
             for element in parser.parse(line.to_string()) {
-                stack.evaluate(element);
+                stack.evaluate(element).unwrap();
             }
         }
 
@@ -336,4 +892,436 @@ if
 
         assert_eq!(stack.list, vec![Element::Number(3.0)]);
     }
+
+    #[test]
+    fn test_instruction_limit() {
+        let mut stack = Stack::new();
+        stack.set_instruction_limit(Some(1000));
+        let mut parser = Parser::new();
+        let mut result = Ok(());
+        for element in parser.parse(String::from("{ 1 } { } while")) {
+            result = stack.evaluate(element);
+        }
+
+        assert_eq!(
+            result,
+            Err(RuntimeError::InstructionLimitExceeded { limit: 1000 })
+        );
+        assert_eq!(stack.steps(), 1000);
+    }
+
+    #[test]
+    fn test_steps() {
+        let mut parser = Parser::new();
+        let mut iter = parser.parse(String::from("1 2 +"));
+        let stack = parse(&mut iter);
+
+        assert_eq!(stack.steps(), 3);
+    }
+
+    fn evaluate_with_limits(code: &str, limits: Limits) -> Result<(), RuntimeError> {
+        let mut stack = Stack::new();
+        stack.set_limits(limits);
+        let mut parser = Parser::new();
+        parser
+            .parse(code.to_string())
+            .try_for_each(|element| stack.evaluate(element))
+    }
+
+    #[test]
+    fn test_stack_depth_limit() {
+        let limits = Limits {
+            stack_depth: Some(10),
+            ..Limits::unlimited()
+        };
+
+        assert_eq!(
+            evaluate_with_limits("{ 1 } { 1 } while", limits),
+            Err(RuntimeError::StackDepthExceeded { limit: 10 })
+        );
+    }
+
+    #[test]
+    fn test_dictionary_depth_limit() {
+        let limits = Limits {
+            dictionary_depth: Some(10),
+            ..Limits::unlimited()
+        };
+
+        assert_eq!(
+            evaluate_with_limits("/f { f } def f", limits),
+            Err(RuntimeError::DictionaryDepthExceeded { limit: 10 })
+        );
+    }
+
+    #[test]
+    fn test_element_limit() {
+        let limits = Limits {
+            elements: Some(100),
+            ..Limits::unlimited()
+        };
+
+        assert_eq!(
+            evaluate_with_limits("{ 1 } { { 1 2 3 } } while", limits),
+            Err(RuntimeError::ElementLimitExceeded { limit: 100 })
+        );
+    }
+
+    #[test]
+    fn test_memory_limit_rejects_before_insert() {
+        let mut stack = Stack::new();
+        // 組み込みの語も数えるので、空のスタックから5個まで入るようにする
+        let limit = stack.usage.elements + 5;
+        stack.set_limits(Limits {
+            elements: Some(limit),
+            ..Limits::unlimited()
+        });
+        let mut parser = Parser::new();
+
+        let result = parser
+            .parse("{ 1 2 3 4 5 6 7 8 }".to_string())
+            .try_for_each(|element| stack.evaluate(element));
+        assert_eq!(result, Err(RuntimeError::ElementLimitExceeded { limit }));
+        assert!(stack.list().is_empty());
+        assert_eq!(stack.usage, stack.measure());
+
+        let result = stack.define(
+            "x",
+            Element::Block(create_block(vec![Element::Integer(1); 8])),
+        );
+        assert_eq!(result, Err(RuntimeError::ElementLimitExceeded { limit }));
+        assert!(stack.lookup("x").is_err());
+        assert_eq!(stack.usage, stack.measure());
+
+        // 上限を超えた要素が残らないので、続けて評価できる
+        assert_eq!(stack.push(1), Ok(()));
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let limits = Limits {
+            bytes: Some(4096),
+            ..Limits::unlimited()
+        };
+
+        assert_eq!(
+            evaluate_with_limits("{ 1 } { /a_long_symbol_name } while", limits),
+            Err(RuntimeError::MemoryLimitExceeded { limit: 4096 })
+        );
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut stack = Stack::new();
+        let code = "/x { 1 2 } def /x 3 def /f { /y { 4 } def y y } def f pop \
+            /m module /z 5 def /z export end /m module /w 6 def end 7 8 9 2 pack pop";
+        evaluate_code(&mut stack, code).unwrap();
+        stack.register("tick", |stack| stack.push(1));
+        stack.unregister("+");
+
+        assert_eq!(stack.usage, stack.measure());
+        assert_eq!(
+            evaluate_code(&mut stack, "/m module /v 1 def /u export end"),
+            Err(RuntimeError::UndefinedWord("m.u".to_string()))
+        );
+        assert_eq!(stack.usage, stack.measure());
+        assert!(evaluate_code(&mut stack, "/g { /n module /v 1 def } def g").is_err());
+        assert_eq!(stack.usage, stack.measure());
+    }
+
+    #[test]
+    fn test_memory_limit_many_pushes() {
+        // 要素を積むたびにスタック全体を数え直すと終わらない数の要素を積む
+        let limits = Limits {
+            elements: Some(1_000_000),
+            bytes: Some(64 * 1024 * 1024),
+            ..Limits::unlimited()
+        };
+
+        assert_eq!(evaluate_with_limits("1 100000 { 1 } for", limits), Ok(()));
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let limits = Limits {
+            outputs: Some(3),
+            ..Limits::unlimited()
+        };
+
+        assert_eq!(
+            evaluate_with_limits("{ 1 } { 1 puts } while", limits),
+            Err(RuntimeError::OutputLimitExceeded { limit: 3 })
+        );
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut stack = Stack::new();
+        let handle = stack.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            handle.interrupt();
+        });
+
+        let mut parser = Parser::new();
+        let result = parser
+            .parse(String::from("{ 1 } { } while"))
+            .try_for_each(|element| stack.evaluate(element));
+        interrupter.join().unwrap();
+
+        assert_eq!(result, Err(RuntimeError::Interrupted));
+        assert!(!stack.interrupt_handle().is_interrupted());
+    }
+
+    fn evaluate_code(stack: &mut Stack, code: &str) -> Result<(), RuntimeError> {
+        let mut parser = Parser::new();
+        parser
+            .parse(code.to_string())
+            .try_for_each(|element| stack.evaluate(element))
+    }
+
+    #[test]
+    fn test_register_closure() {
+        let counter = Rc::new(Cell::new(0));
+        let mut stack = Stack::new();
+        let captured = counter.clone();
+        stack.register("tick", move |stack| {
+            captured.set(captured.get() + 1);
+            stack.push(Element::Integer(captured.get()))
+        });
+
+        evaluate_code(&mut stack, "tick tick tick +").unwrap();
+
+        assert_eq!(counter.get(), 3);
+        assert_eq!(stack.list, vec![Element::Integer(1), Element::Number(5.0)]);
+    }
+
+    #[test]
+    fn test_override_builtin() {
+        let printed = Rc::new(RefCell::new(vec![]));
+        let mut stack = Stack::new();
+        let captured = printed.clone();
+        stack.register("puts", move |stack| {
+            captured.borrow_mut().push(stack.list.pop().unwrap());
+            Ok(())
+        });
+
+        let output = OutputBuffer::new();
+        stack.set_output(Box::new(output.clone()));
+
+        evaluate_code(&mut stack, "1 2 + puts").unwrap();
+
+        assert_eq!(*printed.borrow(), vec![Element::Number(3.0)]);
+        assert!(output.text().is_empty());
+    }
+
+    #[test]
+    fn test_unregister() {
+        let mut stack = Stack::new();
+
+        assert!(stack.unregister("dup").is_some());
+        assert_eq!(
+            evaluate_code(&mut stack, "1 dup"),
+            Err(RuntimeError::UndefinedWord("dup".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_evaluate_loaded_native() {
+        let mut stack = Stack::new();
+
+        assert_eq!(evaluate_code(&mut stack, "3 /dup load 1 pack exec"), Ok(()));
+        assert_eq!(stack.list, vec![Element::Integer(3), Element::Integer(3)]);
+    }
+
+    #[test]
+    fn test_native_error() {
+        let mut stack = Stack::new();
+        stack.register("fail", |_| Err(RuntimeError::Custom("failed".to_string())));
+
+        assert_eq!(
+            evaluate_code(&mut stack, "/f { fail } def f"),
+            Err(RuntimeError::Custom("failed".to_string()))
+        );
+        assert!(stack.call_stack.is_empty());
+        assert_eq!(stack.variables.len(), 1);
+    }
+
+    #[test]
+    fn test_typed_pop() {
+        let mut stack = Stack::new();
+        stack.push(3).unwrap();
+        stack.push(1.5).unwrap();
+        stack.push("text").unwrap();
+
+        assert_eq!(stack.peek(2), Ok(&Element::Integer(3)));
+        assert_eq!(stack.pop_string(), Ok("text".to_string()));
+        assert_eq!(stack.pop_number(), Ok(1.5));
+        assert_eq!(stack.pop_integer(), Ok(3));
+        assert_eq!(stack.pop(), Err(RuntimeError::StackUnderflow));
+        assert_eq!(stack.peek(0), Err(RuntimeError::StackUnderflow));
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let mut stack = Stack::new();
+
+        assert_eq!(
+            evaluate_code(&mut stack, "1 +"),
+            Err(RuntimeError::StackUnderflow)
+        );
+        assert_eq!(
+            evaluate_code(&mut stack, "5 index"),
+            Err(RuntimeError::StackUnderflow)
+        );
+
+        let mut stack = Stack::new();
+        assert_eq!(
+            evaluate_code(&mut stack, "1 exch"),
+            Err(RuntimeError::StackUnderflow)
+        );
+        assert_eq!(stack.list(), &vec![Element::Integer(1)]);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let mut stack = Stack::new();
+
+        assert_eq!(
+            evaluate_code(&mut stack, "1 2 -1 index"),
+            Err(RuntimeError::OutOfRange {
+                name: "index",
+                value: -1
+            })
+        );
+        assert_eq!(
+            evaluate_code(&mut stack, "{ 1 } 1 get"),
+            Err(RuntimeError::OutOfRange {
+                name: "index",
+                value: 1
+            })
+        );
+        assert_eq!(
+            evaluate_code(&mut stack, "-1 pack"),
+            Err(RuntimeError::OutOfRange {
+                name: "count",
+                value: -1
+            })
+        );
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let mut stack = Stack::new();
+
+        assert_eq!(
+            evaluate_code(&mut stack, "1 { 2 } +"),
+            Err(RuntimeError::TypeMismatch {
+                expected: "number",
+                found: Element::Block(create_block(vec![Element::Integer(2)])),
+            })
+        );
+        assert_eq!(
+            evaluate_code(&mut stack, "1 2 def"),
+            Err(RuntimeError::TypeMismatch {
+                expected: "symbol",
+                found: Element::Integer(1),
+            })
+        );
+        assert_eq!(
+            evaluate_code(&mut stack, "3 length"),
+            Err(RuntimeError::TypeMismatch {
+                expected: "block or string",
+                found: Element::Integer(3),
+            })
+        );
+    }
+
+    #[test]
+    fn test_print() {
+        let mut stack = Stack::new();
+        let output = OutputBuffer::new();
+        stack.set_output(Box::new(output.clone()));
+
+        let code =
+            r#""a b" = "a b" == 1.5 = 1.5 == { "x" /y 2.0 } == "no newline" print "!" println"#;
+        evaluate_code(&mut stack, code).unwrap();
+
+        assert_eq!(
+            output.text(),
+            "a b\n\"a b\"\n1.5\n1.5\n{ \"x\" /y 2.0 }\nno newline!\n"
+        );
+    }
+
+    #[test]
+    fn test_printf() {
+        let mut stack = Stack::new();
+        let output = OutputBuffer::new();
+        stack.set_output(Box::new(output.clone()));
+
+        evaluate_code(&mut stack, r#"1 2 + "x" "{} and {} {{}}\n" printf"#).unwrap();
+
+        assert_eq!(output.text(), "3 and x {}\n");
+        assert!(stack.list.is_empty());
+        assert_eq!(
+            evaluate_code(&mut stack, r#"1 "{} {}" printf"#),
+            Err(RuntimeError::StackUnderflow)
+        );
+        assert_eq!(
+            evaluate_code(&mut stack, r#""{" printf"#),
+            Err(RuntimeError::Custom(
+                r#"invalid format string: "{""#.to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_args_and_exit() {
+        let mut stack = Stack::new();
+        stack.set_args(vec!["a.txt".to_string(), "-v".to_string()]);
+
+        evaluate_code(&mut stack, "argv dup length exch 1 get").unwrap();
+        assert_eq!(
+            stack.list,
+            vec![Element::Integer(2), Element::String("-v".to_string())]
+        );
+        assert_eq!(
+            evaluate_code(&mut stack, "1 3 exit 2"),
+            Err(RuntimeError::Exit(3))
+        );
+        assert_eq!(
+            evaluate_code(&mut stack, "256 exit"),
+            Err(RuntimeError::OutOfRange {
+                name: "exit status",
+                value: 256
+            })
+        );
+        assert_eq!(
+            evaluate_code(&mut stack, "-1 exit"),
+            Err(RuntimeError::OutOfRange {
+                name: "exit status",
+                value: -1
+            })
+        );
+    }
+
+    #[test]
+    fn test_getenv() {
+        let mut stack = Stack::new();
+        let path = std::env::var("PATH").unwrap();
+
+        evaluate_code(
+            &mut stack,
+            r#""PATH" getenv "RUSTACK_UNDEFINED_VARIABLE" getenv"#,
+        )
+        .unwrap();
+        assert_eq!(
+            stack.list,
+            vec![
+                Element::String(path),
+                Element::Integer(1),
+                Element::Integer(0),
+            ]
+        );
+    }
 }
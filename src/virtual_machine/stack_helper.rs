@@ -1,22 +1,428 @@
-macro_rules! impl_operation {
-    {$name:ident, $op:tt} => {
-        fn $name(&mut self) {
-            let rhs = self.list.pop().unwrap().as_number();
-            let lhs = self.list.pop().unwrap().as_number();
-            self.list.push(Element::Number((lhs $op rhs) as f32));
+use crate::element::{Block, Element, NativeOperation, Symbol};
+use crate::native_operation;
+
+use super::error::RuntimeError;
+use super::stack::Stack;
+
+/// 組み込みの演算の名前と実装を返す
+pub(crate) fn builtins() -> [(&'static str, NativeOperation); 39] {
+    [
+        ("+", add()),
+        ("-", subtract()),
+        ("*", multiply()),
+        ("/", divide()),
+        ("<", less_than()),
+        ("if", operate_if()),
+        ("def", operate_define()),
+        ("for", operate_for()),
+        ("while", operate_while()),
+        ("times", times()),
+        ("each", each()),
+        ("map", map()),
+        ("puts", puts()),
+        ("=", print_text()),
+        ("==", print_source()),
+        ("print", print()),
+        ("println", print_text()),
+        ("printf", printf()),
+        ("readline", readline()),
+        ("readnumber", readnumber()),
+        ("readall", readall()),
+        ("argv", argv()),
+        ("getenv", getenv()),
+        ("exit", exit()),
+        ("import", import()),
+        ("include", include()),
+        ("module", module()),
+        ("export", export()),
+        ("end", end()),
+        ("use", use_word()),
+        ("pop", operate_pop()),
+        ("dup", duplicate()),
+        ("exch", exchange()),
+        ("index", index()),
+        ("load", load()),
+        ("exec", exec()),
+        ("length", length()),
+        ("get", get()),
+        ("pack", pack()),
+    ]
+}
+
+native_operation! {
+    /// 加算を行う
+    fn add(lhs: f32, rhs: f32) -> f32 {
+        lhs + rhs
+    }
+}
+
+native_operation! {
+    /// 減算を行う
+    fn subtract(lhs: f32, rhs: f32) -> f32 {
+        lhs - rhs
+    }
+}
+
+native_operation! {
+    /// 乗算を行う
+    fn multiply(lhs: f32, rhs: f32) -> f32 {
+        lhs * rhs
+    }
+}
+
+native_operation! {
+    /// 除算を行う
+    fn divide(lhs: f32, rhs: f32) -> f32 {
+        lhs / rhs
+    }
+}
+
+native_operation! {
+    /// 小なり大小比較を行う
+    fn less_than(lhs: f32, rhs: f32) -> bool {
+        lhs < rhs
+    }
+}
+
+native_operation! {
+    /// 条件分岐を行う
+    fn operate_if(
+        stack: &mut Stack,
+        condition: Block,
+        true_branch: Block,
+        false_branch: Block,
+    ) -> Result<(), RuntimeError> {
+        stack.evaluate_block(&condition)?;
+
+        match stack.pop_integer()? {
+            0 => stack.evaluate_block(&false_branch),
+            _ => stack.evaluate_block(&true_branch),
+        }
+    }
+}
+
+native_operation! {
+    /// 変数定義を行う
+    fn operate_define(stack: &mut Stack, name: Symbol, value: Element) -> Result<(), RuntimeError> {
+        stack.define(&name.0, value)
+    }
+}
+
+native_operation! {
+    /// for文による繰り返し操作を行う
+    fn operate_for(stack: &mut Stack, start: i32, end: i32, body: Block) -> Result<(), RuntimeError> {
+        for _ in start..=end {
+            stack.evaluate_block(&body)?;
+        }
+        Ok(())
+    }
+}
+
+native_operation! {
+    /// while文による繰り返し操作を行う
+    fn operate_while(stack: &mut Stack, condition: Block, body: Block) -> Result<(), RuntimeError> {
+        stack.evaluate_block(&condition)?;
+        while stack.pop_integer()? != 0 {
+            stack.evaluate_block(&body)?;
+            stack.evaluate_block(&condition)?;
+        }
+        Ok(())
+    }
+}
+
+native_operation! {
+    /// ブロックを指定した回数だけ評価する
+    fn times(stack: &mut Stack, count: i32, body: Block) -> Result<(), RuntimeError> {
+        for _ in 0..count {
+            stack.evaluate_block(&body)?;
+        }
+        Ok(())
+    }
+}
+
+/// ブロックの要素を順に積んで本体を評価する
+fn each_element(stack: &mut Stack, list: &Block, body: &Block) -> Result<(), RuntimeError> {
+    for (element, _) in list.iter() {
+        stack.push(element.clone())?;
+        stack.evaluate_block(body)?;
+    }
+    Ok(())
+}
+
+native_operation! {
+    /// ブロックの要素を順に積んで本体を評価する
+    fn each(stack: &mut Stack, list: Block, body: Block) -> Result<(), RuntimeError> {
+        each_element(stack, &list, &body)
+    }
+}
+
+native_operation! {
+    /// ブロックの要素ごとに本体を評価し、結果を要素の数だけまとめたブロックを返す
+    fn map(stack: &mut Stack, list: Block, body: Block) -> Result<Block, RuntimeError> {
+        each_element(stack, &list, &body)?;
+        stack.pop_many(list.len()).map(Block::from_vec)
+    }
+}
+
+native_operation! {
+    /// スタックの先頭を取り出して表示する
+    fn puts(stack: &mut Stack, value: Element) -> Result<(), RuntimeError> {
+        stack.write_element(&value)
+    }
+}
+
+native_operation! {
+    /// スタックの先頭を取り出して改行を付けて表示する
+    fn print_text(stack: &mut Stack, value: Element) -> Result<(), RuntimeError> {
+        stack.write_str(&format!("{}\n", value.to_text()))
+    }
+}
+
+native_operation! {
+    /// スタックの先頭を取り出してソースコードの形式で表示する
+    fn print_source(stack: &mut Stack, value: Element) -> Result<(), RuntimeError> {
+        stack.write_str(&format!("{value}\n"))
+    }
+}
+
+native_operation! {
+    /// スタックの先頭を取り出して改行を付けずに表示する
+    fn print(stack: &mut Stack, value: Element) -> Result<(), RuntimeError> {
+        stack.write_str(&value.to_text())
+    }
+}
+
+native_operation! {
+    /// 書式の `{}` を書式の下にある要素で順に置き換えて表示する (`{{` と `}}` は波括弧にする)
+    fn printf(stack: &mut Stack, format: String) -> Result<(), RuntimeError> {
+        let pieces = split_format(&format)?;
+        let arguments = stack.pop_many(pieces.len() - 1)?;
+
+        let mut text = String::new();
+        for (piece, argument) in pieces.iter().zip(arguments.iter()) {
+            text.push_str(piece);
+            text.push_str(&argument.to_text());
         }
+        text.push_str(pieces.last().unwrap());
+        stack.write_str(&text)
     }
 }
 
-macro_rules! impl_operation_integer {
-    {$name:ident, $op:tt} => {
-        fn $name(&mut self) {
-            let rhs = self.list.pop().unwrap().as_number();
-            let lhs = self.list.pop().unwrap().as_number();
-            self.list.push(Element::Integer((lhs $op rhs) as i32));
+/// 書式を `{}` の前後の文字列に分割する
+fn split_format(format: &str) -> Result<Vec<String>, RuntimeError> {
+    let mut pieces = vec![String::new()];
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                pieces.last_mut().unwrap().push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                pieces.push(String::new());
+            }
+            ('{' | '}', _) => {
+                return Err(RuntimeError::Custom(format!(
+                    "invalid format string: {format:?}"
+                )))
+            }
+            _ => pieces.last_mut().unwrap().push(c),
         }
     }
+    Ok(pieces)
 }
 
-pub(crate) use impl_operation;
-pub(crate) use impl_operation_integer;
+native_operation! {
+    /// 入力元から1行読み込み、行と1を積む (入力の終わりでは0だけを積む)
+    fn readline(stack: &mut Stack) -> Result<(), RuntimeError> {
+        match stack.read_line()? {
+            Some(line) => {
+                stack.push(line)?;
+                stack.push(true)
+            }
+            None => stack.push(false),
+        }
+    }
+}
+
+native_operation! {
+    /// 入力元から1行読み込み、数値と1を積む (入力の終わりでは0だけを積む)
+    fn readnumber(stack: &mut Stack) -> Result<(), RuntimeError> {
+        let Some(line) = stack.read_line()? else {
+            return stack.push(false);
+        };
+        match Element::from_word(line.trim().to_string()) {
+            number @ (Element::Integer(_) | Element::Number(_)) => stack.push(number)?,
+            _ => {
+                return Err(RuntimeError::TypeMismatch {
+                    expected: "number",
+                    found: Element::String(line),
+                })
+            }
+        }
+        stack.push(true)
+    }
+}
+
+native_operation! {
+    /// 入力元の残りをすべて読み込んで文字列として積む (入力の終わりでは空の文字列を積む)
+    fn readall(stack: &mut Stack) -> Result<String, RuntimeError> {
+        stack.read_all()
+    }
+}
+
+native_operation! {
+    /// プログラムに渡したコマンドライン引数を文字列のブロックとして積む
+    fn argv(stack: &mut Stack) -> Block {
+        let args = stack.args().iter().map(|arg| Element::from(arg.as_str()));
+        Block::from_vec(args.collect())
+    }
+}
+
+native_operation! {
+    /// 環境変数の値と1を積む (設定されていない場合は0だけを積む)
+    fn getenv(stack: &mut Stack, name: String) -> Result<(), RuntimeError> {
+        match std::env::var(name) {
+            Ok(value) => {
+                stack.push(value)?;
+                stack.push(true)
+            }
+            Err(_) => stack.push(false),
+        }
+    }
+}
+
+native_operation! {
+    /// 終了コード (0から255) を指定してプログラムを終了する
+    fn exit(status: i32) -> Result<(), RuntimeError> {
+        if !(0..=255).contains(&status) {
+            return Err(RuntimeError::OutOfRange {
+                name: "exit status",
+                value: status,
+            });
+        }
+        Err(RuntimeError::Exit(status))
+    }
+}
+
+native_operation! {
+    /// ファイルを一度だけ読み込んで評価する
+    fn import(stack: &mut Stack, path: String) -> Result<(), RuntimeError> {
+        stack.import(&path, true)
+    }
+}
+
+native_operation! {
+    /// ファイルを読み込むたびに評価する
+    fn include(stack: &mut Stack, path: String) -> Result<(), RuntimeError> {
+        stack.import(&path, false)
+    }
+}
+
+native_operation! {
+    /// 名前空間の定義を開始する
+    fn module(stack: &mut Stack, name: Symbol) -> Result<(), RuntimeError> {
+        stack.begin_namespace(&name.0)
+    }
+}
+
+native_operation! {
+    /// 定義中の名前空間の語を公開する
+    fn export(stack: &mut Stack, name: Symbol) -> Result<(), RuntimeError> {
+        stack.export(&name.0)
+    }
+}
+
+native_operation! {
+    /// 名前空間の定義を終了する
+    fn end(stack: &mut Stack) -> Result<(), RuntimeError> {
+        stack.end_namespace()
+    }
+}
+
+native_operation! {
+    /// 名前空間の公開された語を名前空間なしで呼び出せるようにする
+    fn use_word(stack: &mut Stack, namespace: Symbol, name: Symbol) -> Result<(), RuntimeError> {
+        stack.use_word(&namespace.0, &name.0)
+    }
+}
+
+native_operation! {
+    /// スタックの先頭を取り出す
+    fn operate_pop(value: Element) {
+        drop(value);
+    }
+}
+
+native_operation! {
+    /// スタックの先頭を複製する
+    fn duplicate(value: Element) -> (Element, Element) {
+        (value.clone(), value)
+    }
+}
+
+native_operation! {
+    /// スタックの先頭と先頭から2番目を交換する
+    fn exchange(second: Element, first: Element) -> (Element, Element) {
+        (first, second)
+    }
+}
+
+native_operation! {
+    /// 先頭からn番目 (先頭は0) の要素を複製する
+    fn index(stack: &mut Stack, n: i32) -> Result<Element, RuntimeError> {
+        let n = usize::try_from(n).map_err(|_| RuntimeError::OutOfRange { name: "index", value: n })?;
+        stack.peek(n).cloned()
+    }
+}
+
+native_operation! {
+    /// 語を評価せずに積む
+    fn load(stack: &mut Stack, name: Symbol) -> Result<Element, RuntimeError> {
+        stack.lookup(&name.0)
+    }
+}
+
+native_operation! {
+    /// ブロックを新しい辞書を作らずに評価する
+    fn exec(stack: &mut Stack, body: Block) -> Result<(), RuntimeError> {
+        stack.evaluate_block(&body)
+    }
+}
+
+native_operation! {
+    /// ブロックの要素の数または文字列の文字数を返す
+    fn length(value: Element) -> Result<i32, RuntimeError> {
+        let length = match value {
+            Element::Block(block) => block.len(),
+            Element::String(string) => string.chars().count(),
+            found => {
+                return Err(RuntimeError::TypeMismatch {
+                    expected: "block or string",
+                    found,
+                })
+            }
+        };
+        Ok(length as i32)
+    }
+}
+
+native_operation! {
+    /// ブロックのn番目 (先頭は0) の要素を返す
+    fn get(list: Block, index: i32) -> Result<Element, RuntimeError> {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| list.get(index))
+            .cloned()
+            .ok_or(RuntimeError::OutOfRange { name: "index", value: index })
+    }
+}
+
+native_operation! {
+    /// スタックの先頭からn個の要素をまとめたブロックを返す
+    fn pack(stack: &mut Stack, n: i32) -> Result<Block, RuntimeError> {
+        let n = usize::try_from(n).map_err(|_| RuntimeError::OutOfRange { name: "count", value: n })?;
+        stack.pop_many(n).map(Block::from_vec)
+    }
+}
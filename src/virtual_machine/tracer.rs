@@ -0,0 +1,177 @@
+use std::fmt;
+use std::io::Write;
+
+use crate::element::Element;
+
+use super::stack::Stack;
+
+/// 要素を評価する直前に呼ばれるフック
+pub trait TraceHook {
+    fn trace(&mut self, stack: &Stack, element: &Element);
+}
+
+impl<F> TraceHook for F
+where
+    F: FnMut(&Stack, &Element),
+{
+    fn trace(&mut self, stack: &Stack, element: &Element) {
+        self(stack, element)
+    }
+}
+
+impl fmt::Debug for dyn TraceHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TraceHook")
+    }
+}
+
+/// トレースの出力形式
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TraceFormat {
+    /// 呼び出しの深さで字下げしたテキスト
+    Text,
+    /// 1行に1つのJSONオブジェクト
+    JsonLines,
+}
+
+/// 評価した要素を書き出すトレーサー
+pub struct Tracer<W> {
+    output: W,
+    format: TraceFormat,
+    /// 表示するスタックの先頭の要素の数
+    depth: usize,
+}
+
+impl<W: Write> Tracer<W> {
+    /// トレーサーを生成する
+    pub fn new(output: W, format: TraceFormat) -> Self {
+        Self {
+            output,
+            format,
+            depth: 3,
+        }
+    }
+
+    /// 表示するスタックの先頭の要素の数を設定する
+    pub fn with_stack_depth(self, depth: usize) -> Self {
+        Self { depth, ..self }
+    }
+
+    fn write(&mut self, stack: &Stack, element: &Element) -> std::io::Result<()> {
+        let list = stack.list();
+        let top = &list[list.len().saturating_sub(self.depth)..];
+        let calls = stack.call_stack();
+
+        match self.format {
+            TraceFormat::Text => {
+                let omitted = if top.len() < list.len() { "... " } else { "" };
+                let top: Vec<String> = top.iter().map(Element::to_string).collect();
+                writeln!(
+                    self.output,
+                    "{:>4}: {:indent$}{element}  [{omitted}{}]",
+                    stack.line(),
+                    "",
+                    top.join(" "),
+                    indent = calls.len() * 2,
+                )
+            }
+            TraceFormat::JsonLines => {
+                let top: Vec<String> = top
+                    .iter()
+                    .map(|element| JsonString(&element.to_string()).to_string())
+                    .collect();
+                let word = calls
+                    .last()
+                    .map_or(String::from("null"), |name| JsonString(name).to_string());
+                writeln!(
+                    self.output,
+                    r#"{{"step":{},"line":{},"depth":{},"word":{word},"element":{},"stack":[{}],"stack_size":{}}}"#,
+                    stack.steps(),
+                    stack.line(),
+                    calls.len(),
+                    JsonString(&element.to_string()),
+                    top.join(","),
+                    list.len(),
+                )
+            }
+        }
+    }
+}
+
+impl<W: Write> TraceHook for Tracer<W> {
+    fn trace(&mut self, stack: &Stack, element: &Element) {
+        // トレースの書き込みに失敗しても実行は続ける
+        let _ = self.write(stack, element);
+    }
+}
+
+/// JSONの文字列として書き出す
+struct JsonString<'a>(&'a str);
+
+impl fmt::Display for JsonString<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                '\r' => write!(f, "\\r")?,
+                '\t' => write!(f, "\\t")?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TraceFormat, Tracer};
+    use crate::virtual_machine::{OutputBuffer, VirtualMachine};
+
+    fn trace(source: &str, format: TraceFormat) -> String {
+        let output = OutputBuffer::new();
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_tracer(Some(Box::new(
+            Tracer::new(output.clone(), format).with_stack_depth(2),
+        )));
+        virtual_machine.eval_str(source).unwrap();
+
+        output.text()
+    }
+
+    #[test]
+    fn test_text() {
+        let actual = trace("/double { 2 * } def\n1 3 double", TraceFormat::Text);
+
+        assert_eq!(
+            actual,
+            "   1: /double  []
+   1: { 2 * }  [/double]
+   1: def  [/double { 2 * }]
+   2: 1  []
+   2: 3  [1]
+   2: double  [1 3]
+   1:   2  [1 3]
+   1:   *  [... 3 2]
+"
+        );
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let actual = trace("/f { 1 } def\nf", TraceFormat::JsonLines);
+        let lines: Vec<&str> = actual.lines().collect();
+
+        assert_eq!(
+            lines[4],
+            r#"{"step":5,"line":1,"depth":1,"word":"f","element":"1","stack":[],"stack_size":0}"#
+        );
+        assert_eq!(
+            lines[3],
+            r#"{"step":4,"line":2,"depth":0,"word":null,"element":"f","stack":[],"stack_size":0}"#
+        );
+    }
+}
@@ -1,5 +1,17 @@
-use super::super::element::Element;
-use super::stack::Stack;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use super::super::element::{Element, NativeOperation};
+use super::super::parser::Parser;
+use super::debugger::Debugger;
+use super::error::RuntimeError;
+use super::input::InputSource;
+use super::interrupt::InterruptHandle;
+use super::limits::Limits;
+use super::output::OutputSink;
+use super::profiler::Profiler;
+use super::stack::{Snapshot, Stack};
+use super::tracer::TraceHook;
 
 /// 仮想マシン
 #[derive(Debug)]
@@ -7,17 +19,152 @@ pub struct VirtualMachine {
     stack: Stack,
 }
 
+/// 起動時に読み込む標準の語
+pub const PRELUDE: &str = include_str!("prelude.txt");
+
 impl VirtualMachine {
-    /// 仮想マシンを生成する
+    /// 標準の語を読み込んだ仮想マシンを生成する
     pub fn new() -> Self {
+        let mut virtual_machine = Self::bare();
+        virtual_machine
+            .eval_str(PRELUDE)
+            .expect("failed to load the prelude");
+        virtual_machine.reset_steps();
+        virtual_machine
+    }
+
+    /// 組み込みの演算だけを持つ仮想マシンを生成する
+    pub fn bare() -> Self {
         Self {
             stack: Stack::new(),
         }
     }
 
     ///　要素を評価する
-    pub fn evaluate(&mut self, element: Element) {
-        self.stack.evaluate(element);
+    pub fn evaluate(&mut self, element: Element) -> Result<(), RuntimeError> {
+        let result = self.stack.evaluate(element);
+        self.recover(result)
+    }
+
+    /// 行番号を指定して要素を評価する
+    pub fn evaluate_at(&mut self, element: Element, line: usize) -> Result<(), RuntimeError> {
+        self.stack.set_line(line);
+        let result = self.stack.evaluate(element);
+        self.recover(result)
+    }
+
+    /// エラーで中断した場合は定義中の名前空間を破棄する (以降の定義が名前空間に入らないようにする)
+    fn recover<T>(&mut self, result: Result<T, RuntimeError>) -> Result<T, RuntimeError> {
+        if result.is_err() {
+            self.stack.discard_namespaces(1);
+        }
+        result
+    }
+
+    /// パーサーで1行をパースして評価する (閉じていないブロックは次の行に続く)
+    pub fn eval_line(&mut self, parser: &mut Parser, line: &str) -> Result<(), RuntimeError> {
+        parser
+            .parse(line.to_string())
+            .try_for_each(|element| self.evaluate_at(element, parser.line()))
+    }
+
+    /// 文字列のソースを評価する (ソースの終わりで閉じていないブロックや終わっていない名前空間はエラーにする)
+    pub fn eval_str(&mut self, source: &str) -> Result<(), RuntimeError> {
+        self.stack.begin_run();
+        let depth = self.stack.dictionaries().len();
+        let mut parser = Parser::new();
+        source
+            .lines()
+            .try_for_each(|line| self.eval_line(&mut parser, line))?;
+        self.stack.finish_source(&parser, depth)
+    }
+
+    /// 読み込んだソースを1行ずつ評価する
+    pub fn run_reader(&mut self, source: impl BufRead) -> Result<(), RuntimeError> {
+        self.stack.begin_run();
+        let depth = self.stack.dictionaries().len();
+        let mut parser = Parser::new();
+        for line in source.lines() {
+            let line = line.map_err(|error| RuntimeError::Io(error.to_string()))?;
+            self.eval_line(&mut parser, &line)?;
+        }
+        self.stack.finish_source(&parser, depth)
+    }
+
+    /// ファイルのソースを評価する (ファイルの中の `import` はファイルからの相対パスで探す)
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<(), RuntimeError> {
+        self.stack.begin_run();
+        let result = self.stack.run_file(path.as_ref());
+        self.recover(result)
+    }
+
+    /// `import` でファイルを探すディレクトリを返す
+    pub fn search_path(&self) -> &[PathBuf] {
+        self.stack.search_path()
+    }
+
+    /// `import` でファイルを探すディレクトリを設定する
+    pub fn set_search_path(&mut self, search_path: Vec<PathBuf>) {
+        self.stack.set_search_path(search_path);
+    }
+
+    /// 引数を積んで語を呼び出し、語がスタックに残した要素を奥から順に返す
+    ///
+    /// 語は呼び出す前に積まれていた要素を取り出すことも参照することもできず、[`RuntimeError::StackUnderflow`] になる。
+    /// エラーの場合は呼び出す前のスタックの深さまで要素を取り除く。
+    pub fn call(
+        &mut self,
+        name: &str,
+        args: impl IntoIterator<Item = Element>,
+    ) -> Result<Vec<Element>, RuntimeError> {
+        self.stack.begin_run();
+        let base = self.stack.list().len();
+        let floor = self.stack.set_floor(base);
+        let result = args
+            .into_iter()
+            .try_for_each(|arg| self.stack.push(arg))
+            .and_then(|()| self.stack.execute(name.to_string()));
+        self.stack.set_floor(floor);
+        let result = self.recover(result);
+
+        let left = self.stack.list().len().saturating_sub(base);
+        let elements = self.stack.pop_many(left)?;
+        result.map(|()| elements)
+    }
+
+    /// Rustの関数やクロージャを組み込みの演算として登録する (同じ名前の語は上書きする)
+    pub fn register(
+        &mut self,
+        name: &str,
+        function: impl Fn(&mut Stack) -> Result<(), RuntimeError> + 'static,
+    ) {
+        self.stack.register(name, function);
+    }
+
+    /// [`native_operation!`](crate::native_operation) などで定義した組み込みの演算を登録する (同じ名前の語は上書きする)
+    pub fn register_operation(&mut self, name: &str, native: NativeOperation) {
+        self.stack.register_operation(name, native);
+    }
+
+    /// 組み込みの演算や大域で定義された語を削除し、削除した要素を返す
+    ///
+    /// 信頼できないスクリプトを実行する場合は `import` と `include` を削除すると、スクリプトからファイルを読み込めなくなる
+    /// ([`run_file`](Self::run_file) は引き続き使える)。
+    ///
+    /// ```
+    /// use refactoring_rustack::{RuntimeError, VirtualMachine};
+    ///
+    /// let mut virtual_machine = VirtualMachine::new();
+    /// virtual_machine.unregister("import");
+    /// virtual_machine.unregister("include");
+    ///
+    /// assert_eq!(
+    ///     virtual_machine.eval_str("\"/etc/passwd\" include"),
+    ///     Err(RuntimeError::UndefinedWord("include".to_string()))
+    /// );
+    /// ```
+    pub fn unregister(&mut self, name: &str) -> Option<Element> {
+        self.stack.unregister(name)
     }
 
     /// スタックを返す
@@ -25,13 +172,326 @@ impl VirtualMachine {
         &self.stack
     }
 
-    /// 出力を返す
-    pub fn outputs(&self) -> &Vec<f32> {
-        self.stack.outputs()
+    /// プログラムに渡すコマンドライン引数を設定する
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.stack.set_args(args);
+    }
+
+    /// スタックと定義された語を保存する
+    pub fn snapshot(&self) -> Snapshot {
+        self.stack.snapshot()
+    }
+
+    /// [`snapshot`](Self::snapshot) で保存した状態に戻す
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.stack.restore(snapshot);
+    }
+
+    /// `puts` などの出力先を設定する (既定では標準出力に出力する)
+    pub fn set_output(&mut self, output: Box<dyn OutputSink>) {
+        self.stack.set_output(output);
+    }
+
+    /// `readline` などの入力元を設定する (既定では標準入力から読み込む)
+    pub fn set_input(&mut self, input: Box<dyn InputSource>) {
+        self.stack.set_input(input);
+    }
+
+    /// 評価できる要素の数の上限を設定する (`None` の場合は無制限)
+    ///
+    /// 上限は [`eval_str`](Self::eval_str)、[`run_reader`](Self::run_reader)、[`run_file`](Self::run_file)、
+    /// [`call`](Self::call) の実行ごとに数える。
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.stack.set_instruction_limit(limit);
+    }
+
+    /// 資源の上限を返す
+    pub fn limits(&self) -> &Limits {
+        self.stack.limits()
+    }
+
+    /// 資源の上限を設定する
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.stack.set_limits(limits);
+    }
+
+    /// 別のスレッドから実行を中断するためのハンドルを返す
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.stack.interrupt_handle()
+    }
+
+    /// デバッガを設定する (`None` の場合は取り外す)
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        self.stack.set_debugger(debugger);
+    }
+
+    /// デバッガを返す
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.stack.debugger_mut()
     }
 
-    /// 出力をプリントする
-    pub fn print_outputs(&self) {
-        self.outputs().iter().for_each(|out| println!("{out}"));
+    /// 要素を評価する直前に呼ばれるトレースのフックを設定する (`None` の場合は取り外す)
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn TraceHook>>) {
+        self.stack.set_tracer(tracer);
+    }
+
+    /// プロファイラを設定する (`None` の場合は取り外す)
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.stack.set_profiler(profiler);
+    }
+
+    /// プロファイラを返す
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.stack.profiler()
+    }
+
+    /// プロファイラを取り外して返す
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.stack.take_profiler()
+    }
+
+    /// これまでに評価した要素の数を返す
+    pub fn steps(&self) -> u64 {
+        self.stack.steps()
+    }
+
+    /// 評価した要素の数を0に戻す
+    pub fn reset_steps(&mut self) {
+        self.stack.reset_steps();
+    }
+}
+
+impl Default for VirtualMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtualMachine;
+    use crate::element::{Block, Element};
+    use crate::virtual_machine::{OutputBuffer, RuntimeError};
+
+    fn buffered() -> (VirtualMachine, OutputBuffer) {
+        let output = OutputBuffer::new();
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.set_output(Box::new(output.clone()));
+        (virtual_machine, output)
+    }
+
+    #[test]
+    fn test_eval_str() {
+        let (mut virtual_machine, output) = buffered();
+
+        assert_eq!(
+            virtual_machine.eval_str("/double {\n  2 *\n} def\n3 double puts"),
+            Ok(())
+        );
+        assert_eq!(output.text(), "6\n");
+        assert_eq!(
+            virtual_machine.eval_str("1 undefined"),
+            Err(RuntimeError::UndefinedWord("undefined".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_run_reader() {
+        let (mut virtual_machine, output) = buffered();
+
+        assert_eq!(virtual_machine.run_reader("1 2\n+ puts".as_bytes()), Ok(()));
+        assert_eq!(output.text(), "3\n");
+    }
+
+    #[test]
+    fn test_instruction_limit_per_run() {
+        let (mut virtual_machine, _) = buffered();
+        virtual_machine.set_instruction_limit(Some(10));
+        virtual_machine
+            .eval_str("/six { 1 2 3 4 5 6 } def")
+            .unwrap();
+
+        assert_eq!(virtual_machine.eval_str("1 2 3 4 5 6"), Ok(()));
+        assert_eq!(virtual_machine.eval_str("1 2 3 4 5 6"), Ok(()));
+        assert!(virtual_machine.call("six", []).is_ok());
+        assert_eq!(
+            virtual_machine.eval_str("1 2 3 4 5 6 7 8 9 10 11"),
+            Err(RuntimeError::InstructionLimitExceeded { limit: 10 })
+        );
+    }
+
+    #[test]
+    fn test_unclosed_block() {
+        let (mut virtual_machine, _) = buffered();
+
+        assert_eq!(
+            virtual_machine.eval_str("/f {\n  1"),
+            Err(RuntimeError::UnclosedBlock { line: 1 })
+        );
+        assert_eq!(
+            virtual_machine.run_reader("1\n{ 2 { 3 }".as_bytes()),
+            Err(RuntimeError::UnclosedBlock { line: 2 })
+        );
+    }
+
+    #[test]
+    fn test_run_file() {
+        let (mut virtual_machine, output) = buffered();
+
+        assert_eq!(virtual_machine.run_file("scripts/fibonacci.txt"), Ok(()));
+        assert_eq!(output.text(), "55\n");
+        assert!(matches!(
+            virtual_machine.run_file("scripts/missing.txt"),
+            Err(RuntimeError::Io(message)) if message.starts_with("scripts/missing.txt: ")
+        ));
+    }
+
+    #[test]
+    fn test_call() {
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine
+            .eval_str("/on_tick { dup 2 * exch 1 + } def")
+            .unwrap();
+
+        assert_eq!(
+            virtual_machine.call("on_tick", [Element::Integer(3)]),
+            Ok(vec![Element::Number(6.0), Element::Number(4.0)])
+        );
+        assert_eq!(
+            virtual_machine.call("+", [1.into(), 2.into()]),
+            Ok(vec![Element::Number(3.0)])
+        );
+        assert_eq!(
+            virtual_machine.call("on_tick", []),
+            Err(RuntimeError::StackUnderflow)
+        );
+        assert_eq!(
+            virtual_machine.call("+", [1.into(), Element::Symbol("x".to_string())]),
+            Err(RuntimeError::TypeMismatch {
+                expected: "number",
+                found: Element::Symbol("x".to_string())
+            })
+        );
+        assert_eq!(
+            virtual_machine.call("undefined", [Element::Integer(1)]),
+            Err(RuntimeError::UndefinedWord("undefined".to_string()))
+        );
+        assert!(virtual_machine.stack().list().is_empty());
+    }
+
+    #[test]
+    fn test_call_keeps_caller_elements() {
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine
+            .eval_str("42 { } /eat { pop pop 5 } def /peeker { 1 index } def")
+            .unwrap();
+        let caller = vec![
+            Element::Integer(42),
+            Element::Block(Block::from_vec(vec![])),
+        ];
+
+        assert_eq!(
+            virtual_machine.call("eat", [Element::Integer(3)]),
+            Err(RuntimeError::StackUnderflow)
+        );
+        assert_eq!(
+            virtual_machine.call("+", [Element::Integer(3)]),
+            Err(RuntimeError::StackUnderflow)
+        );
+        assert_eq!(
+            virtual_machine.call("peeker", []),
+            Err(RuntimeError::StackUnderflow)
+        );
+        assert_eq!(virtual_machine.stack().list(), &caller);
+        assert_eq!(virtual_machine.eval_str("pop pop"), Ok(()));
+    }
+
+    #[test]
+    fn test_prelude() {
+        let mut virtual_machine = VirtualMachine::new();
+        let mut call = |code: &str| {
+            virtual_machine.eval_str(code).unwrap();
+            let list = virtual_machine.stack().list().clone();
+            virtual_machine.eval_str("pop").unwrap();
+            list
+        };
+
+        assert_eq!(call("-3 abs"), vec![3.0.into()]);
+        assert_eq!(call("2 5 max"), vec![5.into()]);
+        assert_eq!(call("2 5 min"), vec![2.into()]);
+        assert_eq!(call("3 square"), vec![9.0.into()]);
+        assert_eq!(call("1 3 { 2 * } times"), vec![8.0.into()]);
+        assert_eq!(call("{ 1 2 3.5 } sum"), vec![6.5.into()]);
+        assert_eq!(
+            call("{ 1 2 3 } { square } map"),
+            vec![Element::Block(Block::from_vec(vec![
+                1.0.into(),
+                4.0.into(),
+                9.0.into()
+            ]))]
+        );
+        assert_eq!(VirtualMachine::new().steps(), 0);
+    }
+
+    #[test]
+    fn test_loop_callbacks_see_their_own_words() {
+        let mut virtual_machine = VirtualMachine::new();
+        let mut run = |code: &str| {
+            virtual_machine.eval_str(code).unwrap();
+            let list = virtual_machine.stack().list().clone();
+            virtual_machine
+                .eval_str(&"pop ".repeat(list.len()))
+                .unwrap();
+            list
+        };
+
+        assert_eq!(
+            run("/__each_index 100 def { 1 2 } { __each_index } each"),
+            vec![1.into(), 100.into(), 2.into(), 100.into()]
+        );
+        assert_eq!(
+            run("/__times_body 5 def 3 { __times_body } times"),
+            vec![5.into(), 5.into(), 5.into()]
+        );
+        assert_eq!(
+            run("/__map_body 7 def /body 8 def { 1 2 } { pop __map_body body + } map"),
+            vec![Element::Block(Block::from_vec(vec![
+                15.0.into(),
+                15.0.into()
+            ]))]
+        );
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut virtual_machine = VirtualMachine::new();
+        virtual_machine.eval_str("1 /x 2 def").unwrap();
+        let snapshot = virtual_machine.snapshot();
+
+        virtual_machine
+            .eval_str("pop /x 3 def /y 4 def /m module /z 5 def end")
+            .unwrap();
+        virtual_machine.restore(snapshot);
+
+        assert_eq!(virtual_machine.stack().list(), &vec![Element::Integer(1)]);
+        assert_eq!(virtual_machine.stack().lookup("x"), Ok(Element::Integer(2)));
+        assert_eq!(
+            virtual_machine.stack().lookup("y"),
+            Err(RuntimeError::UndefinedWord("y".to_string()))
+        );
+        assert_eq!(
+            virtual_machine.stack().lookup("m.z"),
+            Err(RuntimeError::UndefinedWord("m.z".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_bare() {
+        let mut virtual_machine = VirtualMachine::bare();
+
+        assert_eq!(
+            virtual_machine.eval_str("3 square"),
+            Err(RuntimeError::UndefinedWord("square".to_string()))
+        );
     }
 }
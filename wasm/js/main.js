@@ -41,7 +41,13 @@ function run() {
 
 function runImage() {
     const code = document.getElementById("input").value;
-    const result = evaluate_image(code);
+    let result;
+    try {
+        result = evaluate_image(code);
+    } catch (error) {
+        document.getElementById("output").value = `error: ${error}\n`;
+        return;
+    }
 
     const blob = new Blob([result]);
     const image = document.getElementById("image");
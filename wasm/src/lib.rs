@@ -1,9 +1,14 @@
 mod utils;
 
+use std::cell::RefCell;
+use std::convert::TryFrom;
 use std::io::Cursor;
+use std::rc::Rc;
 
 use image::GrayImage;
-use refactoring_rustack::{Parser, VirtualMachine};
+use refactoring_rustack::{
+    Element, Limits, OutputBuffer, OutputSink, ReaderSource, RuntimeError, VirtualMachine,
+};
 use wasm_bindgen::prelude::*;
 use web_sys::js_sys::Uint8Array;
 
@@ -15,52 +20,152 @@ use web_sys::js_sys::Uint8Array;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+/// 1回の実行で使用できる資源の上限
+fn limits() -> Limits {
+    Limits {
+        instructions: Some(100_000_000),
+        stack_depth: Some(100_000),
+        dictionary_depth: Some(1_000),
+        elements: Some(1_000_000),
+        bytes: Some(64 * 1024 * 1024),
+        outputs: Some(1_000_000),
+    }
+}
+
 #[wasm_bindgen]
 pub fn set_panic_hook() {
     utils::set_panic_hook();
 }
 
-#[wasm_bindgen]
-pub fn evaluate(code: &str) -> String {
-    let mut virtual_machine = VirtualMachine::new();
-    let mut parser = Parser::new();
+/// `puts` の出力に印を付け、`print` などの文字列と合わせて出力した順に保持する
+#[derive(Clone, Default)]
+struct Transcript(Rc<RefCell<String>>);
 
-    for line in code.lines() {
-        for element in parser.parse(line.to_string()) {
-            virtual_machine.evaluate(element);
-        }
+impl OutputSink for Transcript {
+    fn write_str(&mut self, text: &str) -> std::io::Result<()> {
+        self.0.borrow_mut().push_str(text);
+        Ok(())
+    }
+
+    fn write_element(&mut self, element: &Element) -> std::io::Result<()> {
+        self.write_str(&format!("puts: {}\n", element.to_text()))
     }
+}
 
+/// 出力をメモリに保持し、入力を文字列から読み込む仮想マシンを生成する
+fn virtual_machine(output: Box<dyn OutputSink>, input: &str) -> VirtualMachine {
+    let mut virtual_machine = VirtualMachine::new();
+    virtual_machine.set_limits(limits());
+    virtual_machine.set_output(output);
+    virtual_machine.set_input(Box::new(ReaderSource::from_string(input)));
     virtual_machine
-        .outputs()
-        .into_iter()
-        .map(|out| format!("puts: {out}\n"))
-        .collect()
 }
 
 #[wasm_bindgen]
-pub fn evaluate_image(code: &str) -> Uint8Array {
-    let mut virtual_machine = VirtualMachine::new();
-    let mut parser = Parser::new();
+pub fn evaluate(code: &str) -> String {
+    evaluate_with_input(code, "")
+}
+
+#[wasm_bindgen]
+pub fn evaluate_with_input(code: &str, input: &str) -> String {
+    let transcript = Transcript::default();
+    let mut virtual_machine = virtual_machine(Box::new(transcript.clone()), input);
+    let result = virtual_machine.eval_str(code);
+
+    let mut outputs = transcript.0.borrow().clone();
+    if !outputs.is_empty() && !outputs.ends_with('\n') {
+        outputs.push('\n');
+    }
 
-    for line in code.lines() {
-        for element in parser.parse(line.to_string()) {
-            virtual_machine.evaluate(element);
+    match result {
+        // `0 exit` は正常に終了したものとして扱う
+        Ok(()) | Err(RuntimeError::Exit(0)) => {
+            outputs.push_str(&format!("steps: {}\n", virtual_machine.steps()))
         }
+        Err(RuntimeError::Exit(status)) => outputs.push_str(&format!("exit status: {status}\n")),
+        Err(error) => outputs.push_str(&format!("error: {error}\n")),
     }
 
-    let (width_and_height, pixels) = virtual_machine.outputs().split_at(2);
+    outputs
+}
+
+#[wasm_bindgen]
+pub fn evaluate_image(code: &str) -> Result<Uint8Array, JsValue> {
+    let png = render_image(code).map_err(|message| JsValue::from_str(&message))?;
+
+    Ok(Uint8Array::new(&unsafe { Uint8Array::view(&png) }.into()))
+}
+
+/// 出力した幅、高さ、画素の値からグレースケールの PNG 画像を生成する
+fn render_image(code: &str) -> Result<Vec<u8>, String> {
+    let output = OutputBuffer::new();
+    let mut virtual_machine = virtual_machine(Box::new(output.clone()), "");
+    virtual_machine
+        .eval_str(code)
+        .map_err(|error| error.to_string())?;
+
+    let numbers = output
+        .elements()
+        .into_iter()
+        .map(f32::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| error.to_string())?;
+    if numbers.len() < 2 {
+        return Err("expected width and height before the pixels".to_string());
+    }
+    let (width_and_height, pixels) = numbers.split_at(2);
 
     let width = width_and_height[0] as u32;
     let height = width_and_height[1] as u32;
-    let pixels: Vec<_> = pixels.into_iter().map(|&i| i as u8).collect();
+    let pixels: Vec<_> = pixels.iter().map(|&i| i as u8).collect();
+    let count = pixels.len();
 
-    let image = GrayImage::from_vec(width, height, pixels).expect("failed to convert pixels");
+    let image = GrayImage::from_vec(width, height, pixels).ok_or_else(|| {
+        format!(
+            "expected {} pixels for {width}x{height}, found {count}",
+            u64::from(width) * u64::from(height)
+        )
+    })?;
 
     let mut result: Cursor<Vec<u8>> = Cursor::new(Vec::new());
     image
         .write_to(&mut result, image::ImageFormat::Png)
-        .expect("Error occurs when writing to buffer");
+        .map_err(|error| error.to_string())?;
+    Ok(result.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate_with_input, render_image};
+
+    #[test]
+    fn test_evaluate_with_input() {
+        let outputs = evaluate_with_input(
+            "1 puts \"a\" print \"b\" println readline pop println \"c\" print",
+            "line\n",
+        );
+        assert!(outputs.starts_with("puts: 1\nab\nline\nc\nsteps: "));
 
-    Uint8Array::new(&unsafe { Uint8Array::view(&result.into_inner()) }.into())
+        assert_eq!(
+            evaluate_with_input("\"x\" print undefined", ""),
+            "x\nerror: \"undefined\" is undefined\n"
+        );
+        assert!(evaluate_with_input("1 puts 0 exit 2 puts", "").starts_with("puts: 1\nsteps: "));
+        assert_eq!(evaluate_with_input("3 exit", ""), "exit status: 3\n");
+    }
+
+    #[test]
+    fn test_render_image() {
+        let png = render_image("2 puts 1 puts 0 puts 255 puts").unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+
+        assert_eq!(
+            render_image("2 puts"),
+            Err("expected width and height before the pixels".to_string())
+        );
+        assert_eq!(
+            render_image("2 puts 2 puts 0 puts"),
+            Err("expected 4 pixels for 2x2, found 1".to_string())
+        );
+    }
 }
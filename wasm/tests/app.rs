@@ -1,3 +1,5 @@
+#![allow(clippy::eq_op)]
+
 use wasm_bindgen_test::{wasm_bindgen_test_configure, wasm_bindgen_test};
 use futures::prelude::*;
 use wasm_bindgen::JsValue;